@@ -0,0 +1,232 @@
+// phase correlation: find how far image 2 is shifted from image 1
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy)]
+struct Complex {
+  re: f64,
+  im: f64,
+}
+
+impl Complex {
+  fn mul(self, other: Complex) -> Complex {
+    Complex {
+      re: self.re * other.re - self.im * other.im,
+      im: self.re * other.im + self.im * other.re,
+    }
+  }
+
+  fn conj(self) -> Complex {
+    Complex {
+      re: self.re,
+      im: -self.im,
+    }
+  }
+}
+
+// in-place iterative radix-2 fft; len must be a power of two
+fn fft(data: &mut [Complex], inverse: bool) {
+  let n = data.len();
+
+  // bit reversal permutation
+  let mut j = 0;
+  for i in 1..n {
+    let mut bit = n >> 1;
+    while j & bit != 0 {
+      j ^= bit;
+      bit >>= 1;
+    }
+    j |= bit;
+    if i < j {
+      data.swap(i, j);
+    }
+  }
+
+  let sign = if inverse { 1.0 } else { -1.0 };
+  let mut len = 2;
+  while len <= n {
+    let angle = sign * 2.0 * PI / len as f64;
+    let step = Complex {
+      re: angle.cos(),
+      im: angle.sin(),
+    };
+    for start in (0..n).step_by(len) {
+      let mut w = Complex { re: 1.0, im: 0.0 };
+      for k in 0..len / 2 {
+        let even = data[start + k];
+        let odd = data[start + k + len / 2].mul(w);
+        data[start + k] = Complex {
+          re: even.re + odd.re,
+          im: even.im + odd.im,
+        };
+        data[start + k + len / 2] = Complex {
+          re: even.re - odd.re,
+          im: even.im - odd.im,
+        };
+        w = w.mul(step);
+      }
+    }
+    len <<= 1;
+  }
+
+  if inverse {
+    for value in data.iter_mut() {
+      value.re /= n as f64;
+      value.im /= n as f64;
+    }
+  }
+}
+
+// rows then columns
+fn fft_2d(data: &mut [Complex], width: usize, height: usize, inverse: bool) {
+  for row in data.chunks_exact_mut(width) {
+    fft(row, inverse);
+  }
+
+  let mut column = vec![Complex { re: 0.0, im: 0.0 }; height];
+  for x in 0..width {
+    for y in 0..height {
+      column[y] = data[y * width + x];
+    }
+    fft(&mut column, inverse);
+    for y in 0..height {
+      data[y * width + x] = column[y];
+    }
+  }
+}
+
+// luminance of an rgba buffer, mean removed and zero padded to power of two dimensions
+fn to_spectrum_input(data: &[u8], width: u32, height: u32, fft_width: usize) -> Vec<Complex> {
+  let luma: Vec<f64> = data
+    .chunks_exact(4)
+    .map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64)
+    .collect();
+  let mean = luma.iter().sum::<f64>() / luma.len().max(1) as f64;
+
+  let fft_height = (height as usize).next_power_of_two();
+  let mut grid = vec![Complex { re: 0.0, im: 0.0 }; fft_width * fft_height];
+  for y in 0..height as usize {
+    for x in 0..width as usize {
+      grid[y * fft_width + x].re = luma[y * width as usize + x] - mean;
+    }
+  }
+  grid
+}
+
+// integer (dx, dy) to move b by so it lines up with a. both buffers are rgba of the same size
+pub fn estimate_shift(a: &[u8], b: &[u8], width: u32, height: u32) -> (i32, i32) {
+  let fft_width = (width as usize).next_power_of_two();
+  let fft_height = (height as usize).next_power_of_two();
+
+  let mut spectrum_a = to_spectrum_input(a, width, height, fft_width);
+  let mut spectrum_b = to_spectrum_input(b, width, height, fft_width);
+  fft_2d(&mut spectrum_a, fft_width, fft_height, false);
+  fft_2d(&mut spectrum_b, fft_width, fft_height, false);
+
+  // normalised cross-power spectrum keeps only the phase difference
+  let mut cross: Vec<Complex> = spectrum_a
+    .iter()
+    .zip(spectrum_b.iter())
+    .map(|(a, b)| {
+      let product = a.mul(b.conj());
+      let magnitude = (product.re * product.re + product.im * product.im).sqrt();
+      match magnitude > f64::EPSILON {
+        true => Complex {
+          re: product.re / magnitude,
+          im: product.im / magnitude,
+        },
+        false => Complex { re: 0.0, im: 0.0 },
+      }
+    })
+    .collect();
+  fft_2d(&mut cross, fft_width, fft_height, true);
+
+  let mut peak = 0;
+  for i in 1..cross.len() {
+    if cross[i].re > cross[peak].re {
+      peak = i;
+    }
+  }
+
+  // the correlation wraps around, so the far half means a negative shift
+  let (px, py) = ((peak % fft_width) as i32, (peak / fft_width) as i32);
+  let dx = if px > fft_width as i32 / 2 {
+    px - fft_width as i32
+  } else {
+    px
+  };
+  let dy = if py > fft_height as i32 / 2 {
+    py - fft_height as i32
+  } else {
+    py
+  };
+  (dx, dy)
+}
+
+// move an image by (dx, dy); uncovered pixels are transparent
+pub fn shift_image(image: &DynamicImage, dx: i32, dy: i32) -> DynamicImage {
+  let (width, height) = image.dimensions();
+  let source = image.to_rgba8();
+  let mut shifted = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 0]));
+
+  for (x, y, pixel) in source.enumerate_pixels() {
+    let (new_x, new_y) = (x as i64 + dx as i64, y as i64 + dy as i64);
+    if new_x >= 0 && new_y >= 0 && new_x < width as i64 && new_y < height as i64 {
+      shifted.put_pixel(new_x as u32, new_y as u32, *pixel);
+    }
+  }
+
+  DynamicImage::ImageRgba8(shifted)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rng::XorShift;
+
+  fn noise(width: u32, height: u32) -> DynamicImage {
+    let mut data = vec![0u8; (width * height * 4) as usize];
+    XorShift::new(11).fill_bytes(&mut data);
+    for pixel in data.chunks_exact_mut(4) {
+      pixel[3] = 255;
+    }
+    DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, data).unwrap())
+  }
+
+  #[test]
+  fn inverse_fft_returns_the_input() {
+    let input: Vec<Complex> = (0..16)
+      .map(|i| Complex {
+        re: (i * 7 % 5) as f64 - 2.0,
+        im: (i % 3) as f64,
+      })
+      .collect();
+    let mut data = input.clone();
+    fft(&mut data, false);
+    fft(&mut data, true);
+    for (a, b) in input.iter().zip(data.iter()) {
+      assert!((a.re - b.re).abs() < 1e-9 && (a.im - b.im).abs() < 1e-9);
+    }
+  }
+
+  #[test]
+  fn recovers_known_shifts() {
+    // 60x40 pads to 64x64, so the padding is exercised too
+    let image = noise(60, 40);
+    for (dx, dy) in [(0, 0), (5, 3), (-7, 2), (4, -6), (-3, -9)] {
+      let shifted = shift_image(&image, dx, dy);
+      let (a, b) = (image.to_rgba8().into_raw(), shifted.to_rgba8().into_raw());
+      // the shift that moves the shifted copy back onto the original
+      assert_eq!(estimate_shift(&a, &b, 60, 40), (-dx, -dy));
+    }
+  }
+
+  #[test]
+  fn uncovered_pixels_are_transparent() {
+    let shifted = shift_image(&noise(8, 6), 3, -2).to_rgba8();
+    for (x, y, pixel) in shifted.enumerate_pixels() {
+      let uncovered = x < 3 || y >= 4;
+      assert_eq!(pixel[3] == 0, uncovered, "pixel {},{}", x, y);
+    }
+  }
+}
@@ -1,22 +1,1082 @@
-fn get_nth_arg(n: usize) -> String {
-  std::env::args().nth(n).unwrap()
+use crate::filters::{DropShadow, SortAxis};
+use crate::grid;
+use crate::pair::{Jitter, Mirror, SizePolicy};
+use crate::tile::Anchor;
+use crate::ImageDataErrors;
+use combiner::blend::{BlendMode, Colormap, Direction, Rounding};
+use image::{imageops::FilterType, ColorType, ImageFormat, Rgba};
+
+fn invalid(flag: &str, problem: String) -> ImageDataErrors {
+  ImageDataErrors::InvalidArgument(flag.to_string(), problem)
+}
+
+fn get_next_value(
+  args: &mut impl Iterator<Item = String>,
+  flag: &str,
+) -> Result<String, ImageDataErrors> {
+  match args.next() {
+    Some(value) => Ok(value),
+    None => Err(ImageDataErrors::MissingArgument(format!(
+      "value for {}",
+      flag
+    ))),
+  }
+}
+
+fn parse_filter(value: &str) -> Result<FilterType, ImageDataErrors> {
+  match value {
+    "nearest" => Ok(FilterType::Nearest),
+    "triangle" => Ok(FilterType::Triangle),
+    "catmullrom" => Ok(FilterType::CatmullRom),
+    "gaussian" => Ok(FilterType::Gaussian),
+    "lanczos3" => Ok(FilterType::Lanczos3),
+    _ => Err(invalid("--filter", format!("unknown filter {}", value))),
+  }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, flag: &str) -> Result<T, ImageDataErrors> {
+  match value.parse() {
+    Ok(number) => Ok(number),
+    Err(_) => Err(invalid(flag, format!("invalid number {}", value))),
+  }
+}
+
+// "#rrggbb" or "#rrggbbaa"
+fn parse_hex_color(value: &str, flag: &str) -> Result<Rgba<u8>, ImageDataErrors> {
+  let bad_color = || {
+    invalid(
+      flag,
+      format!("invalid color {}, expected #rrggbb or #rrggbbaa", value),
+    )
+  };
+  let hex = value.trim_start_matches('#');
+  if hex.len() != 6 && hex.len() != 8 {
+    return Err(bad_color());
+  }
+
+  let mut rgba = [255u8; 4];
+  for (i, channel) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+    *channel = match u8::from_str_radix(hex.get(i * 2..i * 2 + 2).ok_or_else(bad_color)?, 16) {
+      Ok(c) => c,
+      Err(_) => return Err(bad_color()),
+    };
+  }
+  Ok(Rgba(rgba))
+}
+
+// "WxH", also used for cols x rows
+fn parse_dimensions(value: &str, flag: &str) -> Result<(u32, u32), ImageDataErrors> {
+  match value.split_once('x') {
+    Some((width, height)) => Ok((parse_number(width, flag)?, parse_number(height, flag)?)),
+    None => Err(invalid(
+      flag,
+      format!("expected WIDTHxHEIGHT, got {}", value),
+    )),
+  }
+}
+
+fn parse_weight(value: &str, flag: &str) -> Result<f32, ImageDataErrors> {
+  let weight: f32 = parse_number(value, flag)?;
+  if !(0.0..=1.0).contains(&weight) {
+    return Err(invalid(
+      flag,
+      format!("must be between 0 and 1, got {}", value),
+    ));
+  }
+  Ok(weight)
+}
+
+// "r,g,b,a"
+fn parse_weights(value: &str, flag: &str) -> Result<[f32; 4], ImageDataErrors> {
+  let weights = value
+    .split(',')
+    .map(|w| parse_weight(w.trim(), flag))
+    .collect::<Result<Vec<f32>, _>>()?;
+  match weights[..] {
+    [r, g, b, a] => Ok([r, g, b, a]),
+    _ => Err(invalid(
+      flag,
+      format!("expected four comma separated weights, got {}", value),
+    )),
+  }
+}
+
+// numbers with a lower (and optionally upper) bound
+fn parse_in_range<T>(
+  value: &str,
+  flag: &str,
+  range: std::ops::RangeInclusive<T>,
+) -> Result<T, ImageDataErrors>
+where
+  T: std::str::FromStr + PartialOrd + std::fmt::Display,
+{
+  let number = parse_number(value, flag)?;
+  match range.contains(&number) {
+    true => Ok(number),
+    false => Err(invalid(
+      flag,
+      format!(
+        "must be between {} and {}, got {}",
+        range.start(),
+        range.end(),
+        number
+      ),
+    )),
+  }
+}
+
+// flags that only matter for some blend modes
+struct ModeSettings {
+  key_color: Rgba<u8>,
+  key_tolerance: f32,
+  weight: f32,
+  weights: [f32; 4],
+  direction: Direction,
+  image_2_on_top: bool,
+  colormap: Colormap,
+}
+
+// modes with settings of their own are built once every flag has been read
+fn parse_blend_mode(value: &str, settings: &ModeSettings) -> Result<BlendMode, ImageDataErrors> {
+  let mode = match value {
+    "alternate" => BlendMode::Alternate,
+    "average" => BlendMode::Average,
+    "over" => BlendMode::Over {
+      image_2_on_top: settings.image_2_on_top,
+    },
+    "chroma-key" => BlendMode::ChromaKey {
+      key: settings.key_color,
+      tolerance: settings.key_tolerance,
+    },
+    "weighted" => BlendMode::Weighted(settings.weight),
+    "per-channel-weighted" => BlendMode::PerChannelWeighted(settings.weights),
+    "gradient" => BlendMode::Gradient(settings.direction),
+    "heatmap" => BlendMode::Heatmap(settings.colormap),
+    "hue" => BlendMode::Hue,
+    "saturation" => BlendMode::Saturation,
+    "color" => BlendMode::Color,
+    "luminosity" => BlendMode::Luminosity,
+    _ => {
+      return Err(invalid(
+        "--blend-mode",
+        format!("unknown blend mode {}", value),
+      ))
+    }
+  };
+  Ok(mode)
+}
+
+fn parse_direction(value: &str) -> Result<Direction, ImageDataErrors> {
+  match value {
+    "horizontal" => Ok(Direction::Horizontal),
+    "vertical" => Ok(Direction::Vertical),
+    "radial" => Ok(Direction::Radial),
+    _ => Err(invalid(
+      "--gradient-blend",
+      format!("expected horizontal, vertical or radial, got {}", value),
+    )),
+  }
+}
+
+fn parse_colormap(value: &str) -> Result<Colormap, ImageDataErrors> {
+  match value {
+    "viridis" => Ok(Colormap::Viridis),
+    "jet" => Ok(Colormap::Jet),
+    "grayscale" => Ok(Colormap::Grayscale),
+    _ => Err(invalid(
+      "--colormap",
+      format!("expected viridis, jet or grayscale, got {}", value),
+    )),
+  }
+}
+
+// "offset,blur,opacity,color"; the offset is N for both axes or DXxDY
+fn parse_drop_shadow(value: &str, flag: &str) -> Result<DropShadow, ImageDataErrors> {
+  let fields: Vec<&str> = value.split(',').map(str::trim).collect();
+  let (offset, blur, opacity, color) = match fields[..] {
+    [offset, blur, opacity, color] => (offset, blur, opacity, color),
+    _ => {
+      return Err(invalid(
+        flag,
+        format!("expected offset,blur,opacity,color, got {}", value),
+      ))
+    }
+  };
+  let offset = match offset.split_once('x') {
+    Some((dx, dy)) => (parse_number(dx, flag)?, parse_number(dy, flag)?),
+    None => {
+      let both = parse_number(offset, flag)?;
+      (both, both)
+    }
+  };
+  Ok(DropShadow {
+    offset,
+    blur: parse_number(blur, flag)?,
+    opacity: parse_weight(opacity, flag)?,
+    color: parse_hex_color(color, flag)?,
+  })
+}
+
+fn parse_top(value: &str) -> Result<bool, ImageDataErrors> {
+  match value {
+    "1" => Ok(false),
+    "2" => Ok(true),
+    _ => Err(invalid("--top", format!("expected 1 or 2, got {}", value))),
+  }
+}
+
+fn parse_rounding(value: &str) -> Result<Rounding, ImageDataErrors> {
+  match value {
+    "nearest" => Ok(Rounding::Nearest),
+    "truncate" => Ok(Rounding::Truncate),
+    _ => Err(invalid(
+      "--rounding",
+      format!("expected nearest or truncate, got {}", value),
+    )),
+  }
+}
+
+fn parse_color_type(value: &str) -> Result<ColorType, ImageDataErrors> {
+  match value {
+    "l8" => Ok(ColorType::L8),
+    "la8" => Ok(ColorType::La8),
+    "rgb8" => Ok(ColorType::Rgb8),
+    "rgba8" => Ok(ColorType::Rgba8),
+    "l16" => Ok(ColorType::L16),
+    "la16" => Ok(ColorType::La16),
+    "rgb16" => Ok(ColorType::Rgb16),
+    "rgba16" => Ok(ColorType::Rgba16),
+    _ => Err(invalid(
+      "--color-type",
+      format!(
+        "expected l8, la8, rgb8, rgba8 or a 16 bit one, got {}",
+        value
+      ),
+    )),
+  }
+}
+
+fn parse_bit_depth(value: &str) -> Result<u8, ImageDataErrors> {
+  match value {
+    "8" => Ok(8),
+    "16" => Ok(16),
+    _ => Err(invalid(
+      "--output-bitdepth",
+      format!("expected 8 or 16, got {}", value),
+    )),
+  }
+}
+
+fn parse_sort_axis(value: &str) -> Result<SortAxis, ImageDataErrors> {
+  match value {
+    "rows" => Ok(SortAxis::Rows),
+    "cols" => Ok(SortAxis::Cols),
+    _ => Err(invalid(
+      "--sort-pixels",
+      format!("expected rows or cols, got {}", value),
+    )),
+  }
+}
+
+fn parse_mirror(value: &str) -> Result<Mirror, ImageDataErrors> {
+  match value {
+    "h" => Ok(Mirror::Horizontal),
+    "v" => Ok(Mirror::Vertical),
+    _ => Err(invalid(
+      "--mirror",
+      format!("expected h or v, got {}", value),
+    )),
+  }
+}
+
+fn parse_anchor(value: &str) -> Result<Anchor, ImageDataErrors> {
+  match value {
+    "top-left" => Ok(Anchor::TopLeft),
+    "top" => Ok(Anchor::Top),
+    "top-right" => Ok(Anchor::TopRight),
+    "left" => Ok(Anchor::Left),
+    "center" => Ok(Anchor::Center),
+    "right" => Ok(Anchor::Right),
+    "bottom-left" => Ok(Anchor::BottomLeft),
+    "bottom" => Ok(Anchor::Bottom),
+    "bottom-right" => Ok(Anchor::BottomRight),
+    _ => Err(invalid(
+      "--anchor",
+      format!(
+        "expected center, top, bottom, left, right or a corner like top-left, got {}",
+        value
+      ),
+    )),
+  }
+}
+
+// --preset: a starting point for people who don't want to pick every option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+  Web,       // jpeg at quality 80, at most 1920 pixels
+  Print,     // lossless tiff at full size
+  Thumbnail, // small jpeg, at most 256 pixels
+}
+
+impl Preset {
+  // only used when neither --output-format nor the output's extension name one,
+  // otherwise out.png would get jpeg data. the image crate can't encode webp
+  pub fn format(self) -> ImageFormat {
+    match self {
+      Preset::Web | Preset::Thumbnail => ImageFormat::Jpeg,
+      Preset::Print => ImageFormat::Tiff,
+    }
+  }
+}
+
+fn parse_preset(value: &str) -> Result<Preset, ImageDataErrors> {
+  match value {
+    "web" => Ok(Preset::Web),
+    "print" => Ok(Preset::Print),
+    "thumbnail" => Ok(Preset::Thumbnail),
+    _ => Err(invalid(
+      "--preset",
+      format!("expected web, print or thumbnail, got {}", value),
+    )),
+  }
+}
+
+// fills in whatever the command line left unset, so flags win over the preset
+// no matter which side of --preset they're on
+pub fn apply_preset(args: &mut Args, preset: Preset) {
+  let (quality, max_dimension, filter) = match preset {
+    Preset::Web => (Some(80), Some(1920), FilterType::Lanczos3),
+    Preset::Print => (None, None, FilterType::Lanczos3),
+    Preset::Thumbnail => (Some(70), Some(256), FilterType::CatmullRom),
+  };
+  args.quality = args.quality.or(quality);
+  args.max_dimension = args.max_dimension.or(max_dimension);
+  args.filter = args.filter.or(Some(filter));
+}
+
+fn parse_size_policy(value: &str) -> Result<SizePolicy, ImageDataErrors> {
+  match value {
+    "shrink-to-min-area" => Ok(SizePolicy::ShrinkToMinArea),
+    "upscale-to-max-area" => Ok(SizePolicy::UpscaleToMaxArea),
+    _ => Err(invalid(
+      "--normalize-sizes",
+      format!(
+        "expected shrink-to-min-area or upscale-to-max-area, got {}",
+        value
+      ),
+    )),
+  }
+}
+
+// any extension the image crate knows: png, jpg, bmp, ...
+fn parse_output_format(value: &str) -> Result<ImageFormat, ImageDataErrors> {
+  match ImageFormat::from_extension(value) {
+    Some(format) => Ok(format),
+    None => Err(invalid(
+      "--output-format",
+      format!("unknown format {}", value),
+    )),
+  }
+}
+
+fn parse_bool(value: &str, flag: &str) -> Result<bool, ImageDataErrors> {
+  match value {
+    "true" => Ok(true),
+    "false" => Ok(false),
+    _ => Err(invalid(
+      flag,
+      format!("expected true or false, got {}", value),
+    )),
+  }
 }
 
 // different from Args in std::env
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Args {
   pub image_1: String,
   pub image_2: String,
   pub output: String,
+  pub output_format: Option<ImageFormat>, // None -> output extension, then image 1's format
+  pub preset: Option<Preset>,
+  pub quality: Option<u8>, // jpeg only, None -> the encoder's default of 75
+  pub max_dimension: Option<u32>, // longest side of the output, None -> no limit
+  pub filter: Option<FilterType>, // None -> pick default for the resize path
+  pub aa: bool,
+  pub size_policy: SizePolicy,
+  pub background_tile: bool, // image_2 tiled, image_1 on top at its own size; no resizing
+  pub anchor: Anchor,
+  pub canvas: Option<(u32, u32)>, // None -> the larger width and height of the two
+  pub blend_mode: BlendMode,
+  pub rounding: Rounding,
+  pub mask: Option<String>, // grayscale image, black keeps image 1 and white takes image 2
+  pub mask_from_diff: bool,
+  pub mask_threshold: Option<u8>, // hard diff mask: 255 above, 0 otherwise
+  pub mask_feather: u32,          // box blur radius for the mask
+  pub alpha_from: Option<String>, // grayscale image that becomes the output's alpha
+  pub premultiply: bool,
+  pub drop_shadow: Option<DropShadow>, // under the top image of over
+  pub output_bitdepth: Option<u8>,     // None -> follow the inputs
+  pub color_type: Option<ColorType>,   // None -> rgba at the output bit depth
+  pub stats: bool,
+  pub auto_align: bool,
+  pub manifest: Option<String>, // batch mode, replaces the positional paths
+  pub fail_fast: bool,
+  pub timeout_per_image: Option<u64>, // seconds per batch job
+  pub integer_scale: bool,
+  pub compare: bool, // side-by-side sheet instead of a blend
+  pub divider_width: u32,
+  pub divider_color: Rgba<u8>,
+  pub label_1: Option<String>,
+  pub label_2: Option<String>,
+  pub label_color: Rgba<u8>,
+  pub exif_preserve: bool,
+  pub embed_sources: bool,           // input paths as png text chunks
+  pub preview_scale: u32,            // 1 -> full resolution
+  pub assume_srgb: bool,             // skip the colour space check
+  pub strict: bool,                  // differing icc profiles are an error, not a warning
+  pub benchmark: Option<(u32, u32)>, // random in-memory inputs of this size, no files
+  pub validate_only: bool,           // decode the inputs and stop, the output path is optional
+  pub iterations: u32,
+  pub json: bool,     // machine readable reports
+  pub frame_1: usize, // frame of an animated image_1
+  pub frame_2: usize,
+  pub frame_blend: bool,      // average every frame of animated inputs
+  pub posterize: Option<u16>, // levels per channel
+  pub pixelate: Option<u32>,  // block size
+  pub sort_pixels: Option<SortAxis>,
+  pub sort_low: u8, // luma band whose runs get sorted
+  pub sort_high: u8,
+  pub auto_contrast: Option<f32>, // percent clipped at each end
+  pub edge_1: bool,               // replace image_1 with its sobel edges
+  pub edge_2: bool,
+  pub round_corners: Option<u32>,    // corner radius in pixels
+  pub threshold: Option<Option<u8>>, // black and white output, Some(None) -> otsu
+  pub quantize: Option<u16>,         // palette size
+  pub strip_alpha: bool,
+  pub skip_empty: bool, // a fully transparent input -> save the other one as is
+  pub verify: bool,     // read the saved file back and check it
+  pub lead_sharper: bool,
+  pub jitter: Jitter,               // per row shift of the alternate pattern
+  pub split_output: Option<String>, // path prefix for the per-source alternate images
+  pub verbose: bool,
+  pub log_command: Option<String>, // append a json line per run to this file
+  pub limit_memory: Option<u64>,   // MB
+  pub bg_color: Option<Rgba<u8>>, // with strip_alpha: composite over this instead of dropping alpha
+  pub mirror: Option<Mirror>,     // image 2 is a flipped image 1, only one input path
+  pub grid: Option<(u32, u32)>,   // cols x rows contact sheet of `inputs`, only an output path
+  pub inputs: Vec<String>,
+  pub grid_cell: Option<(u32, u32)>, // None -> smallest input
+  pub gutter: u32,
+  pub grid_bg: Rgba<u8>,
+  pub grid_truncate: bool, // drop inputs that don't fit instead of failing
+}
+
+// paths the job will read, checked up front so a typo fails before any work
+fn check_readable(path: &str) -> Result<(), ImageDataErrors> {
+  match std::fs::File::open(path) {
+    Ok(_) => Ok(()),
+    Err(e) => Err(ImageDataErrors::UnreadableInput(path.to_string(), e)),
+  }
+}
+
+fn conflict(flag_1: &str, flag_2: &str) -> ImageDataErrors {
+  ImageDataErrors::ConflictingArguments(flag_1.to_string(), flag_2.to_string())
 }
 
 impl Args {
   // constructor
-  pub fn new() -> Self {
-    Args {
-      image_1: get_nth_arg(1),
-      image_2: get_nth_arg(2),
-      output: get_nth_arg(3),
+  pub fn try_new() -> Result<Self, ImageDataErrors> {
+    Args::parse(std::env::args().skip(1))
+  }
+
+  // everything after the program name
+  pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, ImageDataErrors> {
+    let mut parsed = Args {
+      image_1: String::new(),
+      image_2: String::new(),
+      output: String::new(),
+      output_format: None,
+      filter: None,
+      aa: false,
+      size_policy: SizePolicy::ShrinkToMinArea,
+      preset: None,
+      quality: None,
+      max_dimension: None,
+      background_tile: false,
+      anchor: Anchor::Center,
+      canvas: None,
+      blend_mode: BlendMode::Alternate,
+      rounding: Rounding::Nearest,
+      mask: None,
+      mask_from_diff: false,
+      mask_threshold: None,
+      mask_feather: 0,
+      alpha_from: None,
+      premultiply: false,
+      drop_shadow: None,
+      output_bitdepth: None,
+      color_type: None,
+      stats: false,
+      auto_align: false,
+      manifest: None,
+      fail_fast: true,
+      timeout_per_image: None,
+      integer_scale: false,
+      compare: false,
+      divider_width: 4,
+      divider_color: Rgba([255, 255, 255, 255]),
+      label_1: None,
+      label_2: None,
+      label_color: Rgba([255, 255, 255, 255]),
+      exif_preserve: false,
+      embed_sources: false,
+      preview_scale: 1,
+      assume_srgb: false,
+      strict: false,
+      benchmark: None,
+      validate_only: false,
+      iterations: 10,
+      json: false,
+      frame_1: 0,
+      frame_2: 0,
+      frame_blend: false,
+      posterize: None,
+      pixelate: None,
+      sort_pixels: None,
+      sort_low: 64,
+      sort_high: 192,
+      auto_contrast: None,
+      edge_1: false,
+      edge_2: false,
+      round_corners: None,
+      threshold: None,
+      quantize: None,
+      strip_alpha: false,
+      skip_empty: false,
+      verify: false,
+      lead_sharper: false,
+      jitter: Jitter::default(),
+      split_output: None,
+      verbose: false,
+      log_command: None,
+      limit_memory: None,
+      bg_color: None,
+      mirror: None,
+      grid: None,
+      inputs: Vec::new(),
+      grid_cell: None,
+      gutter: 4,
+      grid_bg: Rgba([255, 255, 255, 255]),
+      grid_truncate: false,
+    };
+    let mut positional: Vec<String> = Vec::new();
+    let mut blend_mode = None;
+    let mut settings = ModeSettings {
+      key_color: Rgba([0, 255, 0, 255]),
+      key_tolerance: 0.1,
+      weight: 0.5,
+      weights: [0.5; 4],
+      direction: Direction::Horizontal,
+      image_2_on_top: false,
+      colormap: Colormap::Jet,
+    };
+
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "--output-format" => {
+          parsed.output_format = Some(parse_output_format(&get_next_value(&mut args, &arg)?)?)
+        }
+        "--filter" => parsed.filter = Some(parse_filter(&get_next_value(&mut args, &arg)?)?),
+        "--preset" => parsed.preset = Some(parse_preset(&get_next_value(&mut args, &arg)?)?),
+        "--quality" => {
+          parsed.quality = Some(parse_in_range(
+            &get_next_value(&mut args, &arg)?,
+            &arg,
+            1..=100,
+          )?)
+        }
+        "--max-dimension" => {
+          parsed.max_dimension = Some(parse_in_range(
+            &get_next_value(&mut args, &arg)?,
+            &arg,
+            1..=u32::MAX,
+          )?)
+        }
+        "--aa" => parsed.aa = true,
+        "--normalize-sizes" => {
+          parsed.size_policy = parse_size_policy(&get_next_value(&mut args, &arg)?)?
+        }
+        "--background-tile" => parsed.background_tile = true,
+        "--anchor" => parsed.anchor = parse_anchor(&get_next_value(&mut args, &arg)?)?,
+        "--canvas" => {
+          let (width, height) = parse_dimensions(&get_next_value(&mut args, &arg)?, &arg)?;
+          if width == 0 || height == 0 {
+            return Err(invalid(&arg, String::from("can't be empty")));
+          }
+          parsed.canvas = Some((width, height))
+        }
+        "--blend-mode" => blend_mode = Some(get_next_value(&mut args, &arg)?),
+        "--rounding" => parsed.rounding = parse_rounding(&get_next_value(&mut args, &arg)?)?,
+        "--mask" => parsed.mask = Some(get_next_value(&mut args, &arg)?),
+        "--mask-from-diff" => parsed.mask_from_diff = true,
+        "--mask-threshold" => {
+          parsed.mask_threshold = Some(parse_number(&get_next_value(&mut args, &arg)?, &arg)?)
+        }
+        "--mask-feather" => {
+          parsed.mask_feather = parse_number(&get_next_value(&mut args, &arg)?, &arg)?
+        }
+        "--alpha-from" => parsed.alpha_from = Some(get_next_value(&mut args, &arg)?),
+        "--premultiply" => parsed.premultiply = true,
+        "--output-bitdepth" => {
+          parsed.output_bitdepth = Some(parse_bit_depth(&get_next_value(&mut args, &arg)?)?)
+        }
+        "--color-type" => {
+          parsed.color_type = Some(parse_color_type(&get_next_value(&mut args, &arg)?)?)
+        }
+        "--key-color" => {
+          settings.key_color = parse_hex_color(&get_next_value(&mut args, &arg)?, &arg)?
+        }
+        "--key-tolerance" => {
+          settings.key_tolerance = parse_number(&get_next_value(&mut args, &arg)?, &arg)?
+        }
+        "--drop-shadow" => {
+          parsed.drop_shadow = Some(parse_drop_shadow(&get_next_value(&mut args, &arg)?, &arg)?)
+        }
+        "--top" => settings.image_2_on_top = parse_top(&get_next_value(&mut args, &arg)?)?,
+        // a weight on its own picks its mode, unless --blend-mode says otherwise
+        "--weight" => {
+          settings.weight = parse_weight(&get_next_value(&mut args, &arg)?, &arg)?;
+          blend_mode.get_or_insert(String::from("weighted"));
+        }
+        "--weights" => {
+          settings.weights = parse_weights(&get_next_value(&mut args, &arg)?, &arg)?;
+          blend_mode.get_or_insert(String::from("per-channel-weighted"));
+        }
+        "--heatmap" => {
+          blend_mode.get_or_insert(String::from("heatmap"));
+        }
+        "--colormap" => settings.colormap = parse_colormap(&get_next_value(&mut args, &arg)?)?,
+        "--gradient-blend" => {
+          settings.direction = parse_direction(&get_next_value(&mut args, &arg)?)?;
+          blend_mode.get_or_insert(String::from("gradient"));
+        }
+        "--stats" => parsed.stats = true,
+        "--auto-align" => {
+          if !cfg!(feature = "auto-align") {
+            return Err(invalid(
+              &arg,
+              String::from("needs the auto-align feature: cargo run --features auto-align"),
+            ));
+          }
+          parsed.auto_align = true
+        }
+        "--manifest" => parsed.manifest = Some(get_next_value(&mut args, &arg)?),
+        "--fail-fast" => parsed.fail_fast = parse_bool(&get_next_value(&mut args, &arg)?, &arg)?,
+        // the spelling in the batch docs, --fail-fast=false
+        flag if flag.starts_with("--fail-fast=") => {
+          let value = flag.split_once('=').map_or("", |(_, value)| value);
+          parsed.fail_fast = parse_bool(value, "--fail-fast")?
+        }
+        "--timeout-per-image" => {
+          parsed.timeout_per_image = Some(parse_in_range(
+            &get_next_value(&mut args, &arg)?,
+            &arg,
+            1..=u64::MAX,
+          )?)
+        }
+        "--integer-scale" => parsed.integer_scale = true,
+        "--compare" => parsed.compare = true,
+        "--divider-width" => {
+          parsed.divider_width = parse_number(&get_next_value(&mut args, &arg)?, &arg)?
+        }
+        "--divider-color" => {
+          parsed.divider_color = parse_hex_color(&get_next_value(&mut args, &arg)?, &arg)?
+        }
+        "--label1" => parsed.label_1 = Some(get_next_value(&mut args, &arg)?),
+        "--label2" => parsed.label_2 = Some(get_next_value(&mut args, &arg)?),
+        "--label-color" => {
+          parsed.label_color = parse_hex_color(&get_next_value(&mut args, &arg)?, &arg)?
+        }
+        "--exif-preserve" => parsed.exif_preserve = true,
+        "--embed-sources" => parsed.embed_sources = true,
+        "--preview-scale" => {
+          parsed.preview_scale =
+            parse_in_range(&get_next_value(&mut args, &arg)?, &arg, 1..=u32::MAX)?
+        }
+        "--assume-srgb" => parsed.assume_srgb = true,
+        "--strict" => parsed.strict = true,
+        // profiles are only detected, there's no colour management to convert with
+        "--to-srgb" => {
+          return Err(invalid(
+            &arg,
+            String::from("not supported in this build, icc profiles are only compared"),
+          ))
+        }
+        "--validate-only" => parsed.validate_only = true,
+        "--benchmark" => {
+          let (width, height) = parse_dimensions(&get_next_value(&mut args, &arg)?, &arg)?;
+          if width == 0 || height == 0 {
+            return Err(invalid(&arg, String::from("can't be empty")));
+          }
+          parsed.benchmark = Some((width, height))
+        }
+        "--iterations" => {
+          parsed.iterations = parse_number(&get_next_value(&mut args, &arg)?, &arg)?;
+          if parsed.iterations == 0 {
+            return Err(invalid(&arg, String::from("needs at least one run")));
+          }
+        }
+        "--json" => parsed.json = true,
+        "--frame1" => parsed.frame_1 = parse_number(&get_next_value(&mut args, &arg)?, &arg)?,
+        "--frame2" => parsed.frame_2 = parse_number(&get_next_value(&mut args, &arg)?, &arg)?,
+        "--frame-blend" => parsed.frame_blend = true,
+        "--posterize" => {
+          parsed.posterize = Some(parse_in_range(
+            &get_next_value(&mut args, &arg)?,
+            &arg,
+            2..=256,
+          )?)
+        }
+        "--pixelate" => {
+          parsed.pixelate = Some(parse_in_range(
+            &get_next_value(&mut args, &arg)?,
+            &arg,
+            1..=u32::MAX,
+          )?)
+        }
+        "--sort-pixels" => {
+          parsed.sort_pixels = Some(parse_sort_axis(&get_next_value(&mut args, &arg)?)?)
+        }
+        "--sort-low" => parsed.sort_low = parse_number(&get_next_value(&mut args, &arg)?, &arg)?,
+        "--sort-high" => parsed.sort_high = parse_number(&get_next_value(&mut args, &arg)?, &arg)?,
+        "--edge-1" => parsed.edge_1 = true,
+        "--edge-2" => parsed.edge_2 = true,
+        "--auto-contrast" => {
+          parsed.auto_contrast.get_or_insert(0.0);
+        }
+        // percentile clip, turns auto-contrast on by itself
+        "--auto-contrast-clip" => {
+          parsed.auto_contrast = Some(parse_in_range(
+            &get_next_value(&mut args, &arg)?,
+            &arg,
+            0.0..=49.0,
+          )?)
+        }
+        "--threshold" => {
+          parsed.threshold = Some(match get_next_value(&mut args, &arg)?.as_str() {
+            "auto" => None,
+            value => Some(parse_number(value, &arg)?),
+          })
+        }
+        "--round-corners" => {
+          parsed.round_corners = Some(parse_number(&get_next_value(&mut args, &arg)?, &arg)?)
+        }
+        "--quantize" => {
+          parsed.quantize = Some(parse_in_range(
+            &get_next_value(&mut args, &arg)?,
+            &arg,
+            2..=256,
+          )?)
+        }
+        "--grid" => {
+          let (cols, rows) = parse_dimensions(&get_next_value(&mut args, &arg)?, &arg)?;
+          if cols == 0 || rows == 0 {
+            return Err(invalid(
+              &arg,
+              String::from("needs at least one column and one row"),
+            ));
+          }
+          parsed.grid = Some((cols, rows))
+        }
+        "--inputs" => {
+          let value = get_next_value(&mut args, &arg)?;
+          parsed.inputs = value.split(',').map(String::from).collect()
+        }
+        "--cell" => {
+          parsed.grid_cell = Some(parse_dimensions(&get_next_value(&mut args, &arg)?, &arg)?)
+        }
+        "--gutter" => parsed.gutter = parse_number(&get_next_value(&mut args, &arg)?, &arg)?,
+        "--grid-bg" => parsed.grid_bg = parse_hex_color(&get_next_value(&mut args, &arg)?, &arg)?,
+        "--grid-truncate" => parsed.grid_truncate = true,
+        "--strip-alpha" => parsed.strip_alpha = true,
+        "--skip-empty" => parsed.skip_empty = true,
+        "--verify" => parsed.verify = true,
+        "--lead-sharper" => parsed.lead_sharper = true,
+        "--jitter" => parsed.jitter.amount = parse_number(&get_next_value(&mut args, &arg)?, &arg)?,
+        "--seed" => parsed.jitter.seed = parse_number(&get_next_value(&mut args, &arg)?, &arg)?,
+        "--split-output" => parsed.split_output = Some(get_next_value(&mut args, &arg)?),
+        "--verbose" => parsed.verbose = true,
+        "--log-command" => parsed.log_command = Some(get_next_value(&mut args, &arg)?),
+        "--limit-memory" => {
+          parsed.limit_memory = Some(parse_number(&get_next_value(&mut args, &arg)?, &arg)?)
+        }
+        "--bg-color" => {
+          parsed.bg_color = Some(parse_hex_color(&get_next_value(&mut args, &arg)?, &arg)?)
+        }
+        "--mirror" => parsed.mirror = Some(parse_mirror(&get_next_value(&mut args, &arg)?)?),
+        // a typo'd option would otherwise turn up as a missing input file
+        flag if flag.starts_with("--") => {
+          return Err(invalid(flag, String::from("unknown option")));
+        }
+        _ => positional.push(arg),
+      }
+    }
+
+    if parsed.quantize.is_some() && parsed.output_bitdepth == Some(16) {
+      return Err(conflict("--quantize", "--output-bitdepth 16"));
+    }
+    // a color type already includes its bit depth and isn't indexed
+    if parsed.color_type.is_some() && parsed.output_bitdepth.is_some() {
+      return Err(conflict("--color-type", "--output-bitdepth"));
+    }
+    if parsed.color_type.is_some() && parsed.quantize.is_some() {
+      return Err(conflict("--color-type", "--quantize"));
+    }
+    // thresholded output is saved as one 8 bit grey channel unless --color-type says otherwise
+    if parsed.threshold.is_some() && parsed.quantize.is_some() {
+      return Err(conflict("--threshold", "--quantize"));
+    }
+    if parsed.threshold.is_some() && parsed.output_bitdepth == Some(16) {
+      return Err(conflict("--threshold", "--output-bitdepth 16"));
+    }
+    if parsed.sort_low > parsed.sort_high {
+      return Err(invalid(
+        "--sort-low",
+        format!(
+          "{} is above --sort-high {}",
+          parsed.sort_low, parsed.sort_high
+        ),
+      ));
+    }
+    if parsed.mask.is_some() && parsed.mask_from_diff {
+      return Err(conflict("--mask", "--mask-from-diff"));
+    }
+    if parsed.alpha_from.is_some() && parsed.strip_alpha {
+      return Err(conflict("--alpha-from", "--strip-alpha"));
+    }
+    if parsed.frame_blend && (parsed.frame_1 != 0 || parsed.frame_2 != 0) {
+      return Err(conflict("--frame-blend", "--frame1/--frame2"));
+    }
+    if parsed.manifest.is_some() && parsed.mirror.is_some() {
+      return Err(conflict("--mirror", "--manifest"));
+    }
+
+    // the tiled layout is always image 1 over image 2
+    if parsed.background_tile {
+      if let Some(mode) = blend_mode.as_deref().filter(|&mode| mode != "over") {
+        return Err(conflict(
+          "--background-tile",
+          &format!("--blend-mode {}", mode),
+        ));
+      }
+      if settings.image_2_on_top {
+        return Err(conflict("--background-tile", "--top 2"));
+      }
+      if parsed.mask.is_some() || parsed.mask_from_diff {
+        return Err(conflict("--background-tile", "--mask/--mask-from-diff"));
+      }
+      if parsed.compare {
+        return Err(conflict("--background-tile", "--compare"));
+      }
+      if parsed.auto_align {
+        return Err(conflict("--background-tile", "--auto-align"));
+      }
+      blend_mode = Some(String::from("over"));
+    }
+    let blend_mode = blend_mode.unwrap_or_else(|| String::from("alternate"));
+    parsed.blend_mode = parse_blend_mode(&blend_mode, &settings)?;
+    // the shadow is cast by the top image, only over has one
+    if parsed.drop_shadow.is_some() {
+      if !matches!(parsed.blend_mode, BlendMode::Over { .. }) {
+        return Err(invalid(
+          "--drop-shadow",
+          String::from("only works with --blend-mode over"),
+        ));
+      }
+      if parsed.mask.is_some() || parsed.mask_from_diff {
+        return Err(conflict("--drop-shadow", "--mask/--mask-from-diff"));
+      }
     }
+    if let BlendMode::Gradient(_) = parsed.blend_mode {
+      if parsed.mask.is_some() || parsed.mask_from_diff {
+        return Err(conflict("--gradient-blend", "--mask/--mask-from-diff"));
+      }
+    }
+
+    // with an explicit --cell the canvas size is known before any input is read
+    if let (Some((cols, rows)), Some(cell)) = (parsed.grid, parsed.grid_cell) {
+      if grid::canvas_size(cols, rows, cell, parsed.gutter).is_none() {
+        return Err(grid::too_large(cols, rows, cell, parsed.gutter));
+      }
+    }
+
+    if parsed.validate_only {
+      if parsed.grid.is_some() {
+        return Err(conflict("--validate-only", "--grid"));
+      }
+      if parsed.benchmark.is_some() {
+        return Err(conflict("--validate-only", "--benchmark"));
+      }
+    }
+
+    if let Some(preset) = parsed.preset {
+      apply_preset(&mut parsed, preset);
+    }
+
+    let mut positional = positional.into_iter();
+    let mut next_positional = |name: &str| match positional.next() {
+      Some(value) => Ok(value),
+      None => Err(ImageDataErrors::MissingArgument(name.to_string())),
+    };
+
+    // a grid takes its images from --inputs and only needs the output path.
+    // the manifest supplies the paths for every job, and benchmarks don't use files
+    if parsed.grid.is_some() {
+      if parsed.inputs.is_empty() {
+        return Err(ImageDataErrors::MissingArgument(String::from(
+          "--inputs for --grid",
+        )));
+      }
+      parsed.output = next_positional("output")?;
+    } else if parsed.manifest.is_none() && parsed.benchmark.is_none() {
+      parsed.image_1 = next_positional("image_1")?;
+      if parsed.mirror.is_none() {
+        parsed.image_2 = next_positional("image_2")?;
+      }
+      // --mirror has no image_2, so a second path under --validate-only can't be
+      // the optional output either, it's the image_2 that --mirror replaces
+      parsed.output = match (parsed.validate_only, parsed.mirror.is_some()) {
+        (true, true) => String::new(),
+        (true, false) => positional.next().unwrap_or_default(),
+        (false, _) => next_positional("output")?,
+      };
+      if parsed.mirror.is_some() && positional.next().is_some() {
+        return Err(conflict("--mirror", "image_2"));
+      }
+    }
+
+    let mut inputs: Vec<&String> = parsed.inputs.iter().collect();
+    inputs.extend(
+      [&parsed.image_1, &parsed.image_2]
+        .into_iter()
+        .filter(|p| !p.is_empty()),
+    );
+    inputs.extend(
+      parsed
+        .mask
+        .iter()
+        .chain(parsed.alpha_from.iter())
+        .chain(parsed.manifest.iter()),
+    );
+    for path in inputs {
+      check_readable(path)?;
+    }
+    Ok(parsed)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(args: &[&str]) -> Result<Args, ImageDataErrors> {
+    Args::parse(args.iter().map(|arg| arg.to_string()))
+  }
+
+  #[test]
+  fn parses_positionals() {
+    let args = parse(&["images/fcc_glyph.png", "images/pro.png", "out.png"]).unwrap();
+    assert_eq!(
+      (
+        args.image_1.as_str(),
+        args.image_2.as_str(),
+        args.output.as_str()
+      ),
+      ("images/fcc_glyph.png", "images/pro.png", "out.png")
+    );
+  }
+
+  #[test]
+  fn missing_positionals() {
+    assert!(matches!(
+      parse(&[]),
+      Err(ImageDataErrors::MissingArgument(what)) if what == "image_1"
+    ));
+    assert!(matches!(
+      parse(&["images/fcc_glyph.png", "images/pro.png"]),
+      Err(ImageDataErrors::MissingArgument(what)) if what == "output"
+    ));
+    assert!(matches!(
+      parse(&["images/fcc_glyph.png", "images/pro.png", "out.png", "--filter"]),
+      Err(ImageDataErrors::MissingArgument(what)) if what == "value for --filter"
+    ));
+  }
+
+  #[test]
+  fn invalid_mode() {
+    assert!(matches!(
+      parse(&["a.png", "b.png", "out.png", "--blend-mode", "sideways"]),
+      Err(ImageDataErrors::InvalidArgument(flag, _)) if flag == "--blend-mode"
+    ));
+  }
+
+  #[test]
+  fn unknown_option() {
+    assert!(matches!(
+      parse(&["a.png", "b.png", "out.png", "--blend-mod", "over"]),
+      Err(ImageDataErrors::InvalidArgument(flag, _)) if flag == "--blend-mod"
+    ));
+  }
+
+  #[test]
+  fn conflicting_flags() {
+    assert!(matches!(
+      parse(&["a.png", "b.png", "out.png", "--quantize", "16", "--output-bitdepth", "16"]),
+      Err(ImageDataErrors::ConflictingArguments(flag_1, _)) if flag_1 == "--quantize"
+    ));
+    assert!(matches!(
+      parse(&["a.png", "out.png", "--mirror", "h", "--compare", "--background-tile"]),
+      Err(ImageDataErrors::ConflictingArguments(flag_1, flag_2))
+        if flag_1 == "--background-tile" && flag_2 == "--compare"
+    ));
+  }
+
+  #[test]
+  fn unreadable_input() {
+    assert!(matches!(
+      parse(&["images/fcc_glyph.png", "images/missing.png", "out.png"]),
+      Err(ImageDataErrors::UnreadableInput(path, _)) if path == "images/missing.png"
+    ));
+  }
+
+  #[test]
+  fn grid_too_large_for_its_cells() {
+    assert!(matches!(
+      parse(&["--grid", "70000x70000", "--cell", "100x100", "--inputs", "images/pro.png", "out.png"]),
+      Err(ImageDataErrors::InvalidArgument(flag, _)) if flag == "--grid"
+    ));
+  }
+
+  #[test]
+  fn to_srgb_is_refused_not_unknown() {
+    assert!(matches!(
+      parse(&["a.png", "b.png", "out.png", "--to-srgb"]),
+      Err(ImageDataErrors::InvalidArgument(flag, why)) if flag == "--to-srgb" && why.starts_with("not supported")
+    ));
+  }
+
+  #[test]
+  fn mirror_refuses_image_2_when_validating() {
+    let conflict = |args: &[&str]| {
+      matches!(
+        parse(args),
+        Err(ImageDataErrors::ConflictingArguments(a, b)) if a == "--mirror" && b == "image_2"
+      )
+    };
+    let (a, b) = ("images/fcc_glyph.png", "images/pro.png");
+    assert!(conflict(&["--mirror", "h", "--validate-only", a, b]));
+    assert!(conflict(&["--mirror", "h", a, b, "out.png"]));
+    assert!(parse(&["--mirror", "h", "--validate-only", a]).is_ok());
+    assert!(parse(&["--mirror", "h", a, "out.png"]).is_ok());
   }
 }
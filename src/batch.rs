@@ -0,0 +1,138 @@
+// run the combine for every "image_1 image_2 output" line of a manifest file
+use crate::args::Args;
+use crate::{temp_path_for, ImageDataErrors};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+pub struct Job {
+  pub image_1: String,
+  pub image_2: String,
+  pub output: String,
+}
+
+// blank lines and lines starting with # are skipped
+pub fn read_manifest(path: &str) -> Result<Vec<Job>, ImageDataErrors> {
+  let contents = match std::fs::read_to_string(path) {
+    Ok(contents) => contents,
+    Err(e) => return Err(ImageDataErrors::UnableToReadManifest(e)),
+  };
+
+  let mut jobs = Vec::new();
+  for line in contents.lines().map(str::trim) {
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields[..] {
+      [image_1, image_2, output] => jobs.push(Job {
+        image_1: image_1.to_string(),
+        image_2: image_2.to_string(),
+        output: output.to_string(),
+      }),
+      _ => return Err(ImageDataErrors::InvalidManifestLine(line.to_string())),
+    }
+  }
+  Ok(jobs)
+}
+
+// with a limit the combine runs on a worker thread. a thread can't be killed, so one that
+// runs over is abandoned: it keeps going in the background and may still write its output
+fn run_job(
+  job_args: Args,
+  process: fn(&Args) -> Result<(), ImageDataErrors>,
+  timeout: Option<u64>,
+) -> Result<(), ImageDataErrors> {
+  let seconds = match timeout {
+    Some(seconds) => seconds,
+    None => return process(&job_args),
+  };
+
+  let (sender, receiver) = mpsc::channel();
+  let worker = thread::spawn(move || {
+    // the receiver is gone once the job timed out, nobody is left to tell
+    let _ = sender.send(process(&job_args));
+  });
+  match receiver.recv_timeout(Duration::from_secs(seconds)) {
+    Ok(result) => result,
+    Err(RecvTimeoutError::Timeout) => Err(ImageDataErrors::TimedOut(seconds)),
+    // the worker panicked before sending; panic here too, like a job without a limit
+    Err(RecvTimeoutError::Disconnected) => match worker.join() {
+      Err(panic) => std::panic::resume_unwind(panic),
+      Ok(()) => unreachable!("worker finished without a result"),
+    },
+  }
+}
+
+// the process is about to exit and take any abandoned workers with it, possibly halfway
+// through a save. their temp files would be left next to the outputs, so clear them out.
+// a worker that already finished has renamed its file away, which is fine too
+fn remove_temp_files(outputs: &[String]) {
+  for output in outputs {
+    let _ = std::fs::remove_file(temp_path_for(output));
+  }
+}
+
+pub fn run_batch(
+  args: &Args,
+  jobs: Vec<Job>,
+  process: fn(&Args) -> Result<(), ImageDataErrors>,
+) -> Result<(), ImageDataErrors> {
+  let mut succeeded = 0;
+  let mut failed: Vec<String> = Vec::new();
+  let mut timed_out: Vec<String> = Vec::new();
+  let mut abandoned: Vec<String> = Vec::new(); // outputs of jobs that may still be writing
+
+  for job in jobs {
+    let mut job_args = args.clone();
+    job_args.image_1 = job.image_1;
+    job_args.image_2 = job.image_2;
+    job_args.output = job.output;
+
+    let job_line = format!(
+      "{} {} {}",
+      job_args.image_1, job_args.image_2, job_args.output
+    );
+    let output = job_args.output.clone();
+    let result = run_job(job_args, process, args.timeout_per_image);
+    if let Err(ImageDataErrors::TimedOut(_)) = result {
+      abandoned.push(output);
+    }
+    match result {
+      Ok(()) => succeeded += 1,
+      // stop on the first error, same as a single run
+      Err(e) if args.fail_fast => {
+        remove_temp_files(&abandoned);
+        return Err(e);
+      }
+      Err(ImageDataErrors::TimedOut(seconds)) => {
+        println!("timed out: {} (over {}s)", job_line, seconds);
+        timed_out.push(job_line);
+      }
+      Err(e) => {
+        println!("failed: {} ({})", job_line, e);
+        failed.push(job_line);
+      }
+    }
+  }
+
+  println!(
+    "{} succeeded, {} failed, {} timed out",
+    succeeded,
+    failed.len(),
+    timed_out.len()
+  );
+  for job_line in &failed {
+    println!("  {}", job_line);
+  }
+  for job_line in &timed_out {
+    println!("  {} (timed out)", job_line);
+  }
+
+  remove_temp_files(&abandoned);
+  match failed.len() + timed_out.len() {
+    0 => Ok(()),
+    count => Err(ImageDataErrors::BatchFailed(count)),
+  }
+}
@@ -0,0 +1,136 @@
+// time the resize + combine steps on random in-memory images, no disk involved
+use crate::args::Args;
+use crate::rng::XorShift;
+use crate::{
+  combine_gradient, combine_images, combine_images_general, resize_filter, standardize_size,
+  standardize_size_general, ImageDataErrors,
+};
+use combiner::blend::BlendMode;
+use image::{DynamicImage, RgbaImage};
+use std::time::{Duration, Instant};
+
+const SEED: u64 = 0x5eed;
+
+// the combined buffer is sized in u32 (see FloatingImage::new), so the inputs have to fit too
+fn random_image(
+  rng: &mut XorShift,
+  width: u32,
+  height: u32,
+) -> Result<DynamicImage, ImageDataErrors> {
+  let length = width
+    .checked_mul(height)
+    .and_then(|pixels| pixels.checked_mul(4))
+    .ok_or_else(|| {
+      ImageDataErrors::InvalidArgument(
+        String::from("--benchmark"),
+        format!("{}x{} is too large for one rgba buffer", width, height),
+      )
+    })?;
+  let mut data = vec![0u8; length as usize];
+  rng.fill_bytes(&mut data);
+  Ok(DynamicImage::ImageRgba8(
+    RgbaImage::from_raw(width, height, data).unwrap(),
+  ))
+}
+
+// how long each of `iterations` runs takes, sorted so min/median/max can be picked off
+fn time(
+  iterations: u32,
+  mut run: impl FnMut() -> Result<(), ImageDataErrors>,
+) -> Result<Vec<Duration>, ImageDataErrors> {
+  let mut timings: Vec<Duration> = Vec::new();
+  for _ in 0..iterations {
+    let start = Instant::now();
+    run()?;
+    timings.push(start.elapsed());
+  }
+  timings.sort();
+  Ok(timings)
+}
+
+pub fn run_benchmark(args: &Args, width: u32, height: u32) -> Result<(), ImageDataErrors> {
+  let mut rng = XorShift::new(SEED);
+  let image_1 = random_image(&mut rng, width, height)?;
+  let image_2 = random_image(&mut rng, width, height)?;
+  let blender = args.blend_mode.blender(args.premultiply, args.rounding);
+  let combine = |image_1: &DynamicImage, image_2: &DynamicImage, general: bool| match (
+    args.blend_mode,
+    general,
+  ) {
+    (BlendMode::Gradient(direction), _) => Ok(combine_gradient(
+      image_1,
+      image_2,
+      direction,
+      args.premultiply,
+      args.rounding,
+      args.preview_scale,
+    )),
+    (_, false) => combine_images(
+      image_1,
+      image_2,
+      blender.as_deref(),
+      args.premultiply,
+      args.preview_scale,
+      args.jitter,
+    ),
+    (_, true) => combine_images_general(
+      image_1,
+      image_2,
+      blender.as_deref(),
+      args.premultiply,
+      args.preview_scale,
+      args.jitter,
+    ),
+  };
+
+  let timings = time(args.iterations, || {
+    let (image_1, image_2) = standardize_size(
+      image_1.clone(),
+      image_2.clone(),
+      resize_filter(args),
+      args.aa,
+      args.integer_scale,
+      args.size_policy,
+    )?;
+    combine(&image_1, &image_2, false)?;
+    Ok(())
+  })?;
+  // the random inputs are equally sized rgba8, so the gap is what the equal size fast path
+  // saves: the 1:1 resample and the rgba8 copies
+  let general = time(args.iterations, || {
+    let (image_1, image_2) = standardize_size_general(
+      image_1.clone(),
+      image_2.clone(),
+      resize_filter(args),
+      args.aa,
+      args.integer_scale,
+      args.size_policy,
+    )?;
+    combine(&image_1, &image_2, true)?;
+    Ok(())
+  })?;
+
+  let millis = |d: &Duration| d.as_secs_f64() * 1000.0;
+  let min = timings.first().map_or(0.0, millis);
+  let median = timings.get(timings.len() / 2).map_or(0.0, millis);
+  let max = timings.last().map_or(0.0, millis);
+  let general_median = general.get(general.len() / 2).map_or(0.0, millis);
+
+  if args.json {
+    println!(
+      "{{\"width\": {}, \"height\": {}, \"iterations\": {}, \"min_ms\": {:.3}, \"median_ms\": {:.3}, \"max_ms\": {:.3}, \"general_median_ms\": {:.3}}}",
+      width, height, args.iterations, min, median, max, general_median
+    );
+  } else {
+    println!("{}x{}, {} iterations", width, height, args.iterations);
+    println!("  min    {:>10.3} ms", min);
+    println!("  median {:>10.3} ms", median);
+    println!("  max    {:>10.3} ms", max);
+    println!(
+      "  median {:>10.3} ms without the equal size fast path ({:.1}x)",
+      general_median,
+      general_median / median.max(f64::EPSILON)
+    );
+  }
+  Ok(())
+}
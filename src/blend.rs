@@ -0,0 +1,717 @@
+use image::Rgba;
+
+/// anything that can merge one pixel of image 1 with one pixel of image 2.
+/// implement this downstream to plug in your own blend mode
+pub trait Blender {
+  fn blend_pixel(&self, a: [u8; 4], b: [u8; 4]) -> [u8; 4];
+}
+
+/// the built-in modes the cli knows about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+  Alternate, // every other pixel from each image
+  Average,   // mean of both pixels
+  // one image composited on top of the other, image 1 unless image_2_on_top
+  Over { image_2_on_top: bool },
+  // pixels of image 1 close to the key colour turn transparent, then over image 2
+  ChromaKey { key: Rgba<u8>, tolerance: f32 },
+  Weighted(f32),                // share of image 2, 0.0 = all image 1
+  PerChannelWeighted([f32; 4]), // same, but one weight per rgba channel
+  // hsl modes: the named component(s) come from image 2, the rest from image 1
+  Hue,
+  Saturation,
+  Color, // hue and saturation
+  Luminosity,
+  Gradient(Direction), // share of image 2 grows across the canvas
+  Heatmap(Colormap),   // how different the pixels are, as a colour
+}
+
+/// which way a gradient blend runs from image 1 to image 2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+  Horizontal, // left edge image 1, right edge image 2
+  Vertical,   // top edge image 1, bottom edge image 2
+  Radial,     // centre image 1, corners image 2
+}
+
+/// colour ramps for [`Heatmap`] and [`heatmap`], from no difference (0.0) to the largest (1.0)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colormap {
+  Viridis,   // dark purple, blue, green, yellow; reads well in greyscale too
+  Jet,       // blue, green, red
+  Grayscale, // black to white
+}
+
+// viridis sampled at 0, 0.25, 0.5, 0.75 and 1, linear in between
+const VIRIDIS: [[f32; 3]; 5] = [
+  [68.0, 1.0, 84.0],
+  [59.0, 82.0, 139.0],
+  [33.0, 145.0, 140.0],
+  [94.0, 201.0, 98.0],
+  [253.0, 231.0, 37.0],
+];
+
+impl Colormap {
+  /// the ramp's colour at `t`, clamped to 0.0-1.0
+  pub fn color(self, t: f32) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0);
+    let rgb = match self {
+      Colormap::Viridis => {
+        let position = t * (VIRIDIS.len() - 1) as f32;
+        let low = (position as usize).min(VIRIDIS.len() - 2);
+        let fraction = position - low as f32;
+        [0, 1, 2].map(|i| VIRIDIS[low][i] + (VIRIDIS[low + 1][i] - VIRIDIS[low][i]) * fraction)
+      }
+      Colormap::Jet => {
+        [3.0, 2.0, 1.0].map(|peak| (1.5 - (4.0 * t - peak).abs()).clamp(0.0, 1.0) * 255.0)
+      }
+      Colormap::Grayscale => [t * 255.0; 3],
+    };
+    rgb.map(|c| Rounding::Nearest.apply(c))
+  }
+}
+
+/// how float blend results become channel values. every blender goes through
+/// [`Rounding::apply`], so a mode never mixes truncation and rounding.
+/// [`premultiply`] and [`unpremultiply`] aren't blends and always round to nearest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+  #[default]
+  Nearest, // halves round up
+  Truncate, // drop the fraction, for matching tools that do
+}
+
+impl Rounding {
+  /// round and clamp to 0-255
+  pub fn apply(self, value: f32) -> u8 {
+    let value = match self {
+      Rounding::Nearest => value.round(),
+      Rounding::Truncate => value.trunc(),
+    };
+    value.clamp(0.0, 255.0) as u8
+  }
+}
+
+/// the image crate hands out straight alpha; scale colour by alpha in place.
+/// rounds to nearest whatever the blend's [`Rounding`]
+pub fn premultiply(data: &mut [u8]) {
+  for pixel in data.chunks_exact_mut(4) {
+    let alpha = pixel[3] as f32 / 255.0;
+    for channel in pixel.iter_mut().take(3) {
+      *channel = (*channel as f32 * alpha).round() as u8;
+    }
+  }
+}
+
+/// undo premultiply; fully transparent pixels have no colour left to recover
+pub fn unpremultiply(data: &mut [u8]) {
+  for pixel in data.chunks_exact_mut(4) {
+    let alpha = pixel[3];
+    for channel in pixel.iter_mut().take(3) {
+      *channel = match alpha {
+        0 => 0,
+        _ => (*channel as f32 * 255.0 / alpha as f32).round().min(255.0) as u8,
+      };
+    }
+  }
+}
+
+impl BlendMode {
+  /// the per-pixel blender for this mode; alternate and gradient are position based so they have none
+  pub fn blender(self, premultiplied: bool, rounding: Rounding) -> Option<Box<dyn Blender>> {
+    match self {
+      BlendMode::Alternate | BlendMode::Gradient(_) => None,
+      BlendMode::Average => Some(Box::new(Average { rounding })),
+      BlendMode::Over { image_2_on_top } => Some(Box::new(Over {
+        premultiplied,
+        rounding,
+        image_2_on_top,
+      })),
+      BlendMode::ChromaKey { key, tolerance } => Some(Box::new(ChromaKey {
+        key,
+        tolerance,
+        premultiplied,
+        rounding,
+      })),
+      BlendMode::Weighted(weight) => Some(Box::new(Weighted {
+        weights: [weight; 4],
+        rounding,
+      })),
+      BlendMode::PerChannelWeighted(weights) => Some(Box::new(Weighted { weights, rounding })),
+      BlendMode::Hue => Some(Box::new(HslBlend::new(HslComponent::Hue, rounding))),
+      BlendMode::Saturation => Some(Box::new(HslBlend::new(HslComponent::Saturation, rounding))),
+      BlendMode::Color => Some(Box::new(HslBlend::new(HslComponent::Color, rounding))),
+      BlendMode::Luminosity => Some(Box::new(HslBlend::new(HslComponent::Luminosity, rounding))),
+      BlendMode::Heatmap(colormap) => Some(Box::new(Heatmap { colormap })),
+    }
+  }
+}
+
+/// linear mix of one channel: 0.0 gives a, 1.0 gives b
+pub fn blend_channel(a: u8, b: u8, weight: f32, rounding: Rounding) -> u8 {
+  rounding.apply(a as f32 * (1.0 - weight) + b as f32 * weight)
+}
+
+/// mean of both pixels
+pub struct Average {
+  pub rounding: Rounding,
+}
+
+impl Blender for Average {
+  fn blend_pixel(&self, a: [u8; 4], b: [u8; 4]) -> [u8; 4] {
+    let mut out = [0u8; 4];
+    for i in 0..4 {
+      out[i] = blend_channel(a[i], b[i], 0.5, self.rounding);
+    }
+    out
+  }
+}
+
+/// porter-duff over with image 1 on top, or image 2 when `image_2_on_top`.
+/// over isn't commutative, so the two orders differ wherever the top pixel is partly transparent.
+/// the straight alpha branch weights colour by the top alpha only: it ignores the bottom alpha
+/// and doesn't divide by the output alpha, so edges over transparency still darken without
+/// `--premultiply`
+pub struct Over {
+  pub premultiplied: bool, // straight colour still has to be weighted by its own alpha
+  pub rounding: Rounding,
+  pub image_2_on_top: bool,
+}
+
+impl Blender for Over {
+  fn blend_pixel(&self, a: [u8; 4], b: [u8; 4]) -> [u8; 4] {
+    let (a, b) = match self.image_2_on_top {
+      true => (b, a),
+      false => (a, b),
+    };
+    let alpha_a = a[3] as f32 / 255.0;
+    let weight_a = if self.premultiplied { 1.0 } else { alpha_a };
+
+    let mut out = [0u8; 4];
+    for i in 0..3 {
+      out[i] = self
+        .rounding
+        .apply(a[i] as f32 * weight_a + b[i] as f32 * (1.0 - alpha_a));
+    }
+    out[3] = self
+      .rounding
+      .apply(a[3] as f32 + b[3] as f32 * (1.0 - alpha_a));
+    out
+  }
+}
+
+/// green screen: zero the alpha of every pixel within `tol` of `key`.
+/// distance is euclidean over rgb, scaled so 0.0 is an exact match and 1.0 is black vs white
+pub fn chroma_key(top: &mut [u8], key: Rgba<u8>, tol: f32) {
+  let max_distance = (3.0f32 * 255.0 * 255.0).sqrt();
+  for pixel in top.chunks_exact_mut(4) {
+    let distance = (0..3)
+      .map(|i| (pixel[i] as f32 - key[i] as f32).powi(2))
+      .sum::<f32>()
+      .sqrt();
+    if distance / max_distance <= tol {
+      pixel[3] = 0;
+    }
+  }
+}
+
+/// mix each channel with its own weight towards image 2
+pub struct Weighted {
+  pub weights: [f32; 4],
+  pub rounding: Rounding,
+}
+
+impl Blender for Weighted {
+  fn blend_pixel(&self, a: [u8; 4], b: [u8; 4]) -> [u8; 4] {
+    let mut out = [0u8; 4];
+    for i in 0..4 {
+      out[i] = blend_channel(a[i], b[i], self.weights[i], self.rounding);
+    }
+    out
+  }
+}
+
+/// chroma key image 1, then composite it over image 2
+pub struct ChromaKey {
+  pub key: Rgba<u8>,
+  pub tolerance: f32,
+  pub premultiplied: bool,
+  pub rounding: Rounding,
+}
+
+impl Blender for ChromaKey {
+  fn blend_pixel(&self, a: [u8; 4], b: [u8; 4]) -> [u8; 4] {
+    let mut top = a;
+    chroma_key(&mut top, self.key, self.tolerance);
+    if top[3] == 0 {
+      top = [0; 4]; // premultiplied colour has to go with the alpha
+    }
+    Over {
+      premultiplied: self.premultiplied,
+      rounding: self.rounding,
+      image_2_on_top: false,
+    }
+    .blend_pixel(top, b)
+  }
+}
+
+/// rec. 601 style luminosity the w3c compositing spec builds its hsl modes on, 0-1
+pub fn lum(rgb: [f32; 3]) -> f32 {
+  0.3 * rgb[0] + 0.59 * rgb[1] + 0.11 * rgb[2]
+}
+
+// pull out of range channels back towards the luminosity, keeping it and the hue
+fn clip_color(rgb: [f32; 3]) -> [f32; 3] {
+  let l = lum(rgb);
+  let min = rgb[0].min(rgb[1]).min(rgb[2]);
+  let max = rgb[0].max(rgb[1]).max(rgb[2]);
+  let mut out = rgb;
+  if min < 0.0 {
+    out = out.map(|c| l + (c - l) * l / (l - min));
+  }
+  if max > 1.0 {
+    out = out.map(|c| l + (c - l) * (1.0 - l) / (max - l));
+  }
+  out
+}
+
+/// shift a 0-1 colour to luminosity `l`, clipping it back into range
+pub fn set_lum(rgb: [f32; 3], l: f32) -> [f32; 3] {
+  let d = l - lum(rgb);
+  clip_color(rgb.map(|c| c + d))
+}
+
+/// max minus min channel, 0-1
+pub fn sat(rgb: [f32; 3]) -> f32 {
+  rgb[0].max(rgb[1]).max(rgb[2]) - rgb[0].min(rgb[1]).min(rgb[2])
+}
+
+/// rescale a 0-1 colour so its [`sat`] is `s`: the smallest channel goes to 0, the largest
+/// to `s` and the middle one keeps its place in between. a grey colour stays black
+pub fn set_sat(rgb: [f32; 3], s: f32) -> [f32; 3] {
+  let mut order = [0, 1, 2];
+  order.sort_by(|&i, &j| rgb[i].total_cmp(&rgb[j]));
+  let [min, mid, max] = order;
+  let mut out = [0.0; 3];
+  if rgb[max] > rgb[min] {
+    out[mid] = (rgb[mid] - rgb[min]) * s / (rgb[max] - rgb[min]);
+    out[max] = s;
+  }
+  out
+}
+
+/// the non-separable hsl modes of the w3c compositing spec, with image 1
+/// as the backdrop and image 2 giving the named component(s)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HslComponent {
+  Hue,
+  Saturation,
+  Color, // hue and saturation
+  Luminosity,
+}
+
+/// image 1 with the named component taken from image 2. alpha stays image 1's
+pub struct HslBlend {
+  pub component: HslComponent,
+  pub rounding: Rounding,
+}
+
+impl HslBlend {
+  pub fn new(component: HslComponent, rounding: Rounding) -> Self {
+    HslBlend {
+      component,
+      rounding,
+    }
+  }
+}
+
+impl Blender for HslBlend {
+  fn blend_pixel(&self, a: [u8; 4], b: [u8; 4]) -> [u8; 4] {
+    let backdrop = [a[0], a[1], a[2]].map(|c| c as f32 / 255.0);
+    let source = [b[0], b[1], b[2]].map(|c| c as f32 / 255.0);
+
+    // a grey image 2 has no hue to give, so taking its hue leaves grey
+    let rgb = match self.component {
+      HslComponent::Hue => set_lum(set_sat(source, sat(backdrop)), lum(backdrop)),
+      HslComponent::Saturation => set_lum(set_sat(backdrop, sat(source)), lum(backdrop)),
+      HslComponent::Color => set_lum(source, lum(backdrop)),
+      HslComponent::Luminosity => set_lum(backdrop, lum(source)),
+    };
+    [
+      self.rounding.apply(rgb[0] * 255.0),
+      self.rounding.apply(rgb[1] * 255.0),
+      self.rounding.apply(rgb[2] * 255.0),
+      a[3],
+    ]
+  }
+}
+
+/// euclidean distance over all four channels, scaled so 0.0 is identical and 1.0 is
+/// transparent black against opaque white
+pub fn diff_magnitude(a: [u8; 4], b: [u8; 4]) -> f32 {
+  let distance = (0..4)
+    .map(|i| (a[i] as f32 - b[i] as f32).powi(2))
+    .sum::<f32>()
+    .sqrt();
+  distance / (4.0f32 * 255.0 * 255.0).sqrt()
+}
+
+// one magnitude as an opaque pixel of the ramp
+fn heat(magnitude: f32, colormap: Colormap) -> [u8; 4] {
+  let [r, g, b] = colormap.color(magnitude);
+  [r, g, b, 255]
+}
+
+/// rgba pixels for a buffer of diff magnitudes, e.g. from [`diff_magnitude`]
+pub fn heatmap(diff_magnitude: &[f32], colormap: Colormap) -> Vec<u8> {
+  diff_magnitude
+    .iter()
+    .flat_map(|&magnitude| heat(magnitude, colormap))
+    .collect()
+}
+
+/// a visual diff: each pixel's [`diff_magnitude`] through the colormap, always opaque
+pub struct Heatmap {
+  pub colormap: Colormap,
+}
+
+impl Blender for Heatmap {
+  fn blend_pixel(&self, a: [u8; 4], b: [u8; 4]) -> [u8; 4] {
+    heat(diff_magnitude(a, b), self.colormap)
+  }
+}
+
+/// mix two rgba buffers by a one byte per pixel mask: 0 is all image 1, 255 all image 2
+pub fn blend_masked(vec_1: &[u8], vec_2: &[u8], mask: &[u8], rounding: Rounding) -> Vec<u8> {
+  let mut combined_data = Vec::with_capacity(vec_1.len());
+
+  for ((a, b), &m) in vec_1.chunks_exact(4).zip(vec_2.chunks_exact(4)).zip(mask) {
+    let weight = m as f32 / 255.0;
+    for i in 0..4 {
+      combined_data.push(blend_channel(a[i], b[i], weight, rounding));
+    }
+  }
+
+  combined_data
+}
+
+/// share of image 2 at pixel (x, y) of a width x height canvas, 0.0 to 1.0 inclusive
+pub fn gradient_weight(direction: Direction, x: u32, y: u32, width: u32, height: u32) -> f32 {
+  let fraction = |position: u32, length: u32| match length {
+    0 | 1 => 0.0,
+    _ => position as f32 / (length - 1) as f32,
+  };
+  match direction {
+    Direction::Horizontal => fraction(x, width),
+    Direction::Vertical => fraction(y, height),
+    Direction::Radial => {
+      let centre_x = width.saturating_sub(1) as f32 / 2.0;
+      let centre_y = height.saturating_sub(1) as f32 / 2.0;
+      let corner = (centre_x * centre_x + centre_y * centre_y).sqrt();
+      if corner == 0.0 {
+        return 0.0; // a single pixel has no edge to fade towards
+      }
+      ((x as f32 - centre_x).hypot(y as f32 - centre_y) / corner).min(1.0)
+    }
+  }
+}
+
+/// mix two width x height rgba buffers with a weight that follows the pixel position
+pub fn blend_gradient(
+  vec_1: &[u8],
+  vec_2: &[u8],
+  width: u32,
+  height: u32,
+  direction: Direction,
+  rounding: Rounding,
+) -> Vec<u8> {
+  let mut combined_data = Vec::with_capacity(vec_1.len());
+
+  for (index, (a, b)) in vec_1.chunks_exact(4).zip(vec_2.chunks_exact(4)).enumerate() {
+    let (x, y) = (index as u32 % width, index as u32 / width);
+    let weight = gradient_weight(direction, x, y, width, height);
+    for i in 0..4 {
+      combined_data.push(blend_channel(a[i], b[i], weight, rounding));
+    }
+  }
+
+  combined_data
+}
+
+/// run a blender over two equally sized rgba buffers
+pub fn blend_pixels(vec_1: &[u8], vec_2: &[u8], blender: &dyn Blender) -> Vec<u8> {
+  let mut combined_data = Vec::with_capacity(vec_1.len());
+
+  for (a, b) in vec_1.chunks_exact(4).zip(vec_2.chunks_exact(4)) {
+    let pixel = blender.blend_pixel([a[0], a[1], a[2], a[3]], [b[0], b[1], b[2], b[3]]);
+    combined_data.extend_from_slice(&pixel);
+  }
+
+  combined_data
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn round_trip(pixel: [u8; 4]) -> [u8; 4] {
+    let mut data = pixel.to_vec();
+    premultiply(&mut data);
+    unpremultiply(&mut data);
+    [data[0], data[1], data[2], data[3]]
+  }
+
+  // a half transparent red edge pixel next to a fully transparent one, blended the way
+  // --premultiply does it: premultiply both, blend, unpremultiply the result
+  fn blend_edge(blender: &dyn Blender, premultiplied: bool) -> [u8; 4] {
+    let (mut edge, mut outside) = (vec![255, 0, 0, 128], vec![0, 0, 0, 0]);
+    if premultiplied {
+      premultiply(&mut edge);
+      premultiply(&mut outside);
+    }
+    let mut out = blend_pixels(&edge, &outside, blender);
+    if premultiplied {
+      unpremultiply(&mut out);
+    }
+    [out[0], out[1], out[2], out[3]]
+  }
+
+  #[test]
+  fn premultiplied_edges_keep_their_colour() {
+    let rounding = Rounding::Nearest;
+    let over = |premultiplied| Over {
+      premultiplied,
+      rounding,
+      image_2_on_top: false,
+    };
+    assert_eq!(blend_edge(&over(true), true), [255, 0, 0, 128]);
+    assert_eq!(blend_edge(&Average { rounding }, true), [255, 0, 0, 64]);
+    // straight alpha darkens the same edge towards the transparent black
+    assert_eq!(blend_edge(&over(false), false), [128, 0, 0, 128]);
+    assert_eq!(blend_edge(&Average { rounding }, false), [128, 0, 0, 64]);
+  }
+
+  #[test]
+  fn premultiply_round_trip_opaque() {
+    assert_eq!(round_trip([12, 128, 250, 255]), [12, 128, 250, 255]);
+  }
+
+  #[test]
+  fn premultiply_round_trip_transparent() {
+    // nothing to recover, the colour goes to black
+    let mut data = vec![12, 128, 250, 0];
+    premultiply(&mut data);
+    assert_eq!(data, [0, 0, 0, 0]);
+    assert_eq!(round_trip([12, 128, 250, 0]), [0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn premultiply_round_trip_alpha_1() {
+    // one step of alpha only has room for off or full colour
+    let mut data = vec![255, 127, 128, 1];
+    premultiply(&mut data);
+    assert_eq!(data, [1, 0, 1, 1]);
+    assert_eq!(round_trip([255, 127, 128, 1]), [255, 0, 255, 1]);
+  }
+
+  #[test]
+  fn premultiply_round_trip_saturated() {
+    for alpha in 0..=255 {
+      let expected = match alpha {
+        0 => [0, 0, 0, 0],
+        _ => [255, 0, 255, alpha],
+      };
+      assert_eq!(
+        round_trip([255, 0, 255, alpha]),
+        expected,
+        "alpha {}",
+        alpha
+      );
+    }
+  }
+
+  #[test]
+  fn chroma_key_clears_key_colour_only() {
+    let green = [0, 255, 0, 255];
+    let near_green = [10, 240, 12, 255];
+    let red = [200, 30, 30, 255];
+    let mut data: Vec<u8> = [green, green, near_green, red].concat();
+    chroma_key(&mut data, Rgba([0, 255, 0, 255]), 0.1);
+    assert_eq!(&data[..12], &[0, 255, 0, 0, 0, 255, 0, 0, 10, 240, 12, 0]);
+    assert_eq!(&data[12..], &red);
+  }
+
+  #[test]
+  fn chroma_key_blender_shows_image_2_through() {
+    let blender = ChromaKey {
+      key: Rgba([0, 255, 0, 255]),
+      tolerance: 0.1,
+      premultiplied: false,
+      rounding: Rounding::Nearest,
+    };
+    let below = [40, 50, 60, 255];
+    assert_eq!(blender.blend_pixel([0, 255, 0, 255], below), below);
+    assert_eq!(
+      blender.blend_pixel([200, 30, 30, 255], below),
+      [200, 30, 30, 255]
+    );
+  }
+
+  #[test]
+  fn rounding_at_halves() {
+    for (value, nearest, truncate) in [
+      (0.5, 1, 0),
+      (1.5, 2, 1),
+      (127.5, 128, 127),
+      (254.5, 255, 254),
+      (255.5, 255, 255), // clamped
+      (-0.5, 0, 0),
+    ] {
+      assert_eq!(Rounding::Nearest.apply(value), nearest, "nearest {}", value);
+      assert_eq!(
+        Rounding::Truncate.apply(value),
+        truncate,
+        "truncate {}",
+        value
+      );
+    }
+  }
+
+  #[test]
+  fn average_follows_rounding() {
+    // 1 and 2 average to 1.5
+    let pixel = |rounding| Average { rounding }.blend_pixel([1, 1, 1, 255], [2, 2, 2, 255]);
+    assert_eq!(pixel(Rounding::Nearest), [2, 2, 2, 255]);
+    assert_eq!(pixel(Rounding::Truncate), [1, 1, 1, 255]);
+  }
+
+  #[test]
+  fn hue_takes_image_2_hue_keeps_image_1_luminosity() {
+    let red = [200, 50, 50, 200];
+    let blender = HslBlend::new(HslComponent::Hue, Rounding::Nearest);
+    let out = blender.blend_pixel(red, [0, 0, 255, 255]);
+    assert_eq!(out, [79, 79, 229, 200]);
+    let luminosity = |p: [u8; 4]| lum([p[0], p[1], p[2]].map(|c| c as f32));
+    assert!((luminosity(out) - luminosity(red)).abs() < 1.0);
+    // grey has no hue to give
+    assert_eq!(
+      blender.blend_pixel(red, [128, 128, 128, 255]),
+      [95, 95, 95, 200]
+    );
+  }
+
+  #[test]
+  fn color_and_luminosity_keep_the_other_side() {
+    let red = [200, 50, 50, 255];
+    let color = HslBlend::new(HslComponent::Color, Rounding::Nearest);
+    assert_eq!(color.blend_pixel(red, [0, 0, 255, 255]), [75, 75, 255, 255]);
+    // clip_color keeps the hue when the luminosity pushes a channel past 255
+    let luminosity = HslBlend::new(HslComponent::Luminosity, Rounding::Nearest);
+    assert_eq!(
+      luminosity.blend_pixel(red, [128, 128, 128, 255]),
+      [233, 83, 83, 255]
+    );
+    assert_eq!(luminosity.blend_pixel(red, [255; 4]), [255; 4]);
+  }
+
+  #[test]
+  fn gradient_weight_runs_from_0_to_1() {
+    let (width, height) = (5, 3);
+    assert_eq!(
+      gradient_weight(Direction::Horizontal, 0, 1, width, height),
+      0.0
+    );
+    assert_eq!(
+      gradient_weight(Direction::Horizontal, 4, 1, width, height),
+      1.0
+    );
+    assert_eq!(
+      gradient_weight(Direction::Horizontal, 2, 1, width, height),
+      0.5
+    );
+    assert_eq!(
+      gradient_weight(Direction::Vertical, 3, 0, width, height),
+      0.0
+    );
+    assert_eq!(
+      gradient_weight(Direction::Vertical, 3, 2, width, height),
+      1.0
+    );
+    assert_eq!(gradient_weight(Direction::Radial, 2, 1, width, height), 0.0);
+    for (x, y) in [(0, 0), (4, 0), (0, 2), (4, 2)] {
+      assert_eq!(gradient_weight(Direction::Radial, x, y, width, height), 1.0);
+    }
+    // nothing to run across
+    assert_eq!(gradient_weight(Direction::Horizontal, 0, 0, 1, 1), 0.0);
+    assert_eq!(gradient_weight(Direction::Radial, 0, 0, 1, 1), 0.0);
+  }
+
+  #[test]
+  fn over_stacking_follows_image_2_on_top() {
+    let over = |image_2_on_top| Over {
+      premultiplied: false,
+      rounding: Rounding::Nearest,
+      image_2_on_top,
+    };
+    let opaque_red = [255, 0, 0, 255];
+    let opaque_blue = [0, 0, 255, 255];
+    // an opaque top pixel hides whatever is below
+    assert_eq!(over(false).blend_pixel(opaque_red, opaque_blue), opaque_red);
+    assert_eq!(over(true).blend_pixel(opaque_red, opaque_blue), opaque_blue);
+
+    // half transparent red over blue, and then the other way round
+    let half_red = [255, 0, 0, 128];
+    assert_eq!(
+      over(false).blend_pixel(half_red, opaque_blue),
+      [128, 0, 127, 255]
+    );
+    assert_eq!(over(true).blend_pixel(half_red, opaque_blue), opaque_blue);
+    assert_eq!(
+      over(true).blend_pixel(opaque_blue, half_red),
+      [128, 0, 127, 255]
+    );
+  }
+
+  #[test]
+  fn heatmap_ramp_endpoints() {
+    assert_eq!(
+      heatmap(&[0.0, 1.0], Colormap::Jet),
+      [0, 0, 128, 255, 128, 0, 0, 255]
+    );
+    // out of range magnitudes clamp to the ends
+    assert_eq!(
+      heatmap(&[-1.0, 0.0, 1.0, 2.0], Colormap::Grayscale),
+      [0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255]
+    );
+    let blender = Heatmap {
+      colormap: Colormap::Jet,
+    };
+    assert_eq!(
+      blender.blend_pixel([9, 9, 9, 9], [9, 9, 9, 9]),
+      [0, 0, 128, 255]
+    );
+    assert_eq!(
+      blender.blend_pixel([0, 0, 0, 0], [255; 4]),
+      [128, 0, 0, 255]
+    );
+  }
+
+  #[test]
+  fn gradient_blend_runs_black_to_white() {
+    // black to white across a 5x3 canvas: every row reads 0, 64, 128, 191, 255
+    let (black, white) = ([0, 0, 0, 255].repeat(15), [255; 4].repeat(15));
+    let out = blend_gradient(
+      &black,
+      &white,
+      5,
+      3,
+      Direction::Horizontal,
+      Rounding::Nearest,
+    );
+    for row in out.chunks_exact(5 * 4) {
+      let reds: Vec<u8> = row.chunks_exact(4).map(|p| p[0]).collect();
+      assert_eq!(reds, [0, 64, 128, 191, 255]);
+    }
+    assert!(out.chunks_exact(4).all(|p| p[3] == 255));
+  }
+}
@@ -0,0 +1,213 @@
+// sniff the colour space out of jpeg/tiff headers; the decoders don't tell us
+// None means rgb/grayscale, which to_rgba8 handles fine
+pub fn detect_color_space(data: &[u8]) -> Option<String> {
+  if data.starts_with(&[0xff, 0xd8]) {
+    jpeg_color_space(data)
+  } else if is_tiff(data) {
+    tiff_color_space(data)
+  } else {
+    None
+  }
+}
+
+// 4 components in the frame header means cmyk (or adobe's ycck)
+fn jpeg_color_space(data: &[u8]) -> Option<String> {
+  let mut i = 2;
+  while i + 4 <= data.len() && data[i] == 0xff {
+    let marker = data[i + 1];
+    let length = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+    let is_frame_header = (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker);
+    if is_frame_header {
+      return match data.get(i + 9) {
+        Some(4) => Some(String::from("CMYK")),
+        _ => None,
+      };
+    }
+    if marker == 0xda {
+      break;
+    }
+    i += 2 + length;
+  }
+  None
+}
+
+// just enough tiff to look up first-ifd tags
+struct Tiff<'a> {
+  data: &'a [u8],
+  big_endian: bool,
+}
+
+impl Tiff<'_> {
+  fn u16_at(&self, at: usize) -> Option<u16> {
+    let bytes = [*self.data.get(at)?, *self.data.get(at + 1)?];
+    Some(match self.big_endian {
+      true => u16::from_be_bytes(bytes),
+      false => u16::from_le_bytes(bytes),
+    })
+  }
+
+  fn u32_at(&self, at: usize) -> Option<u32> {
+    let bytes = [
+      *self.data.get(at)?,
+      *self.data.get(at + 1)?,
+      *self.data.get(at + 2)?,
+      *self.data.get(at + 3)?,
+    ];
+    Some(match self.big_endian {
+      true => u32::from_be_bytes(bytes),
+      false => u32::from_le_bytes(bytes),
+    })
+  }
+
+  // offset of the 12 byte ifd entry for `tag`
+  fn find_tag(&self, tag: u16) -> Option<usize> {
+    let ifd = self.u32_at(4)? as usize;
+    let entries = self.u16_at(ifd)? as usize;
+    (0..entries)
+      .map(|entry| ifd + 2 + entry * 12)
+      .find(|&at| self.u16_at(at) == Some(tag))
+  }
+}
+
+fn is_tiff(data: &[u8]) -> bool {
+  data.starts_with(b"II*\0") || data.starts_with(b"MM\0*")
+}
+
+// PhotometricInterpretation (tag 262) of the first ifd
+fn tiff_color_space(data: &[u8]) -> Option<String> {
+  let tiff = Tiff {
+    data,
+    big_endian: data.starts_with(b"MM"),
+  };
+  let at = tiff.find_tag(262)?;
+  match tiff.u16_at(at + 8)? {
+    0..=3 => None, // white/black is zero, rgb, palette
+    5 => Some(String::from("CMYK")),
+    6 => Some(String::from("YCbCr")),
+    8..=10 => Some(String::from("CIELab")),
+    other => Some(format!("photometric interpretation {}", other)),
+  }
+}
+
+// the embedded icc profile, if any: jpeg APP2 chunks, png iCCP or tiff tag 34675
+pub fn extract_icc(data: &[u8]) -> Option<Vec<u8>> {
+  if data.starts_with(&[0xff, 0xd8]) {
+    jpeg_icc(data)
+  } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+    png_icc(data)
+  } else if is_tiff(data) {
+    tiff_icc(data)
+  } else {
+    None
+  }
+}
+
+// big profiles are split over several APP2 segments, each tagged with its sequence number
+fn jpeg_icc(data: &[u8]) -> Option<Vec<u8>> {
+  const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+  let mut chunks: Vec<(u8, &[u8])> = Vec::new();
+  let mut i = 2;
+  while i + 4 <= data.len() && data[i] == 0xff {
+    let marker = data[i + 1];
+    if marker == 0xda {
+      break;
+    }
+    let length = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+    let payload = data.get(i + 4..i + 2 + length)?;
+    if marker == 0xe2 && payload.starts_with(ICC_HEADER) {
+      let sequence = *payload.get(ICC_HEADER.len())?;
+      chunks.push((sequence, payload.get(ICC_HEADER.len() + 2..)?));
+    }
+    i += 2 + length;
+  }
+  if chunks.is_empty() {
+    return None;
+  }
+
+  chunks.sort_by_key(|(sequence, _)| *sequence);
+  Some(
+    chunks
+      .iter()
+      .flat_map(|(_, chunk)| chunk.to_vec())
+      .collect(),
+  )
+}
+
+// iCCP: name, nul, compression method, then the zlib compressed profile
+fn png_icc(data: &[u8]) -> Option<Vec<u8>> {
+  let mut i = 8;
+  while i + 8 <= data.len() {
+    let length = u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+    let chunk_type = &data[i + 4..i + 8];
+    let chunk = data.get(i + 8..i + 8 + length)?;
+    if chunk_type == b"iCCP" {
+      let name_end = chunk.iter().position(|&b| b == 0)?;
+      let compressed = chunk.get(name_end + 2..)?;
+      return miniz_oxide::inflate::decompress_to_vec_zlib(compressed).ok();
+    }
+    if chunk_type == b"IDAT" {
+      break; // iCCP has to come before the image data
+    }
+    i += 12 + length; // length + type + data + crc
+  }
+  None
+}
+
+fn tiff_icc(data: &[u8]) -> Option<Vec<u8>> {
+  let tiff = Tiff {
+    data,
+    big_endian: data.starts_with(b"MM"),
+  };
+  let at = tiff.find_tag(34675)?;
+  let count = tiff.u32_at(at + 4)? as usize;
+  let offset = match count <= 4 {
+    true => at + 8, // small values sit in the entry itself
+    false => tiff.u32_at(at + 8)? as usize,
+  };
+  data
+    .get(offset..offset + count)
+    .map(|profile| profile.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the headers photoshop writes for a 2x2 cmyk jpeg: adobe APP14, then a 4 component
+  // baseline frame. the scan is left out, detection stops before it
+  fn adobe_cmyk_jpeg() -> Vec<u8> {
+    let mut data = vec![0xff, 0xd8];
+    // APP14: "Adobe", version 100, two flag words, transform 0 (no conversion, cmyk)
+    data.extend_from_slice(&[0xff, 0xee, 0x00, 0x0e]);
+    data.extend_from_slice(b"Adobe");
+    data.extend_from_slice(&[0x00, 0x64, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    // SOF0: 8 bit, 2x2, 4 components each with 1x1 sampling and table 0
+    data.extend_from_slice(&[0xff, 0xc0, 0x00, 0x14, 0x08, 0x00, 0x02, 0x00, 0x02, 0x04]);
+    for component in 1..=4 {
+      data.extend_from_slice(&[component, 0x11, 0x00]);
+    }
+    data.extend_from_slice(&[0xff, 0xd9]);
+    data
+  }
+
+  #[test]
+  fn detects_adobe_cmyk_jpeg() {
+    assert_eq!(
+      detect_color_space(&adobe_cmyk_jpeg()),
+      Some(String::from("CMYK"))
+    );
+  }
+
+  #[test]
+  fn rgb_jpeg_and_srgb_png_pass() {
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut jpeg)
+      .encode(&[255, 0, 0, 0, 255, 0], 2, 1, image::ColorType::Rgb8)
+      .unwrap();
+    assert_eq!(detect_color_space(&jpeg), None);
+    assert_eq!(
+      detect_color_space(include_bytes!("../images/pro.png")),
+      None
+    );
+  }
+}
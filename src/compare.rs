@@ -0,0 +1,69 @@
+// before/after sheets: image 1 | divider | image 2, optionally labelled
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use image::{imageops, DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+const LABEL_SCALE: u32 = 2; // each font pixel becomes a 2x2 block
+const LABEL_MARGIN: u32 = 8;
+
+// draw text with the bundled font, top-left corner at (x, y). clipped at the canvas edge
+pub fn draw_label(canvas: &mut RgbaImage, text: &str, x: u32, y: u32, color: Rgba<u8>) {
+  let advance = (GLYPH_WIDTH + 1) * LABEL_SCALE;
+
+  for (i, c) in text.chars().enumerate() {
+    let glyph_x = x + i as u32 * advance;
+    let glyph = font::glyph(c);
+    for (row, bits) in glyph.iter().enumerate().take(GLYPH_HEIGHT as usize) {
+      for col in 0..GLYPH_WIDTH {
+        if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+          continue;
+        }
+        for dy in 0..LABEL_SCALE {
+          for dx in 0..LABEL_SCALE {
+            let px = glyph_x + col * LABEL_SCALE + dx;
+            let py = y + row as u32 * LABEL_SCALE + dy;
+            if px < canvas.width() && py < canvas.height() {
+              canvas.put_pixel(px, py, color);
+            }
+          }
+        }
+      }
+    }
+  }
+}
+
+// the two images side by side with the divider between them
+pub fn sheet_dimensions((width, height): (u32, u32), divider_width: u32) -> (u32, u32) {
+  (width * 2 + divider_width, height)
+}
+
+// both images are expected to be standardized to the same size already
+pub fn compare_images(
+  image_1: &DynamicImage,
+  image_2: &DynamicImage,
+  divider_width: u32,
+  divider_color: Rgba<u8>,
+  labels: (Option<&str>, Option<&str>),
+  label_color: Rgba<u8>,
+) -> RgbaImage {
+  let (width, height) = image_1.dimensions();
+  let (sheet_width, sheet_height) = sheet_dimensions((width, height), divider_width);
+  let mut canvas = RgbaImage::from_pixel(sheet_width, sheet_height, divider_color);
+
+  let right_x = width + divider_width;
+  imageops::replace(&mut canvas, &image_1.to_rgba8(), 0, 0);
+  imageops::replace(&mut canvas, &image_2.to_rgba8(), right_x, 0);
+
+  if let Some(label) = labels.0 {
+    draw_label(&mut canvas, label, LABEL_MARGIN, LABEL_MARGIN, label_color);
+  }
+  if let Some(label) = labels.1 {
+    draw_label(
+      &mut canvas,
+      label,
+      right_x + LABEL_MARGIN,
+      LABEL_MARGIN,
+      label_color,
+    );
+  }
+  canvas
+}
@@ -0,0 +1,66 @@
+// copy the exif block from image 1 into a jpeg output
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+// the APP1 payload ("Exif\0\0" + tiff data) from a jpeg or png source
+pub fn extract_exif(data: &[u8]) -> Option<Vec<u8>> {
+  if data.starts_with(&[0xff, 0xd8]) {
+    extract_from_jpeg(data)
+  } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+    extract_from_png(data)
+  } else {
+    None
+  }
+}
+
+fn extract_from_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+  let mut i = 2;
+  // walk the marker segments up to start of scan
+  while i + 4 <= data.len() && data[i] == 0xff {
+    let marker = data[i + 1];
+    if marker == 0xda {
+      break;
+    }
+    let length = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+    let payload = data.get(i + 4..i + 2 + length)?;
+    if marker == 0xe1 && payload.starts_with(EXIF_HEADER) {
+      return Some(payload.to_vec());
+    }
+    i += 2 + length;
+  }
+  None
+}
+
+fn extract_from_png(data: &[u8]) -> Option<Vec<u8>> {
+  let mut i = 8;
+  while i + 8 <= data.len() {
+    let length = u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+    let chunk_type = &data[i + 4..i + 8];
+    let chunk = data.get(i + 8..i + 8 + length)?;
+    if chunk_type == b"eXIf" {
+      return Some([EXIF_HEADER, chunk].concat());
+    }
+    i += 12 + length; // length + type + data + crc
+  }
+  None
+}
+
+// insert an APP1 segment right after SOI (and after a JFIF APP0 if there is one)
+pub fn embed_exif(jpeg: &[u8], exif: &[u8]) -> Option<Vec<u8>> {
+  if !jpeg.starts_with(&[0xff, 0xd8]) || exif.len() + 2 > u16::MAX as usize {
+    return None;
+  }
+
+  let mut insert_at = 2;
+  if jpeg.get(2..4) == Some(&[0xff, 0xe0]) {
+    let length = u16::from_be_bytes([*jpeg.get(4)?, *jpeg.get(5)?]) as usize;
+    insert_at += 2 + length;
+  }
+
+  let mut output = Vec::with_capacity(jpeg.len() + exif.len() + 4);
+  output.extend_from_slice(jpeg.get(..insert_at)?);
+  output.extend_from_slice(&[0xff, 0xe1]);
+  output.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+  output.extend_from_slice(exif);
+  output.extend_from_slice(&jpeg[insert_at..]);
+  Some(output)
+}
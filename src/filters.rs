@@ -0,0 +1,509 @@
+// post-filters on the combined rgba buffer, plus the odd pre-filter on an input.
+// alpha is left alone unless stated otherwise
+use crate::ImageDataErrors;
+use image::{DynamicImage, GrayImage, Rgba, RgbaImage};
+
+// quantize each colour channel to `levels` evenly spaced values
+pub fn posterize(data: &mut [u8], levels: u16) {
+  let steps = (levels - 1) as f32;
+  for pixel in data.chunks_exact_mut(4) {
+    for channel in pixel.iter_mut().take(3) {
+      let level = (*channel as f32 / 255.0 * steps).round();
+      *channel = (level * 255.0 / steps).round() as u8;
+    }
+  }
+}
+
+// mosaic: every block x block square takes the mean of its pixels (alpha included).
+// blocks on the right and bottom edge may be smaller
+pub fn pixelate(data: &mut [u8], width: u32, height: u32, block: u32) {
+  let (width, height, block) = (width as usize, height as usize, block as usize);
+  for block_y in (0..height).step_by(block) {
+    for block_x in (0..width).step_by(block) {
+      let rows = block_y..(block_y + block).min(height);
+      let cols = block_x..(block_x + block).min(width);
+      let offsets = || {
+        rows
+          .clone()
+          .flat_map(|y| cols.clone().map(move |x| (y * width + x) * 4))
+      };
+
+      let mut sum = [0u64; 4];
+      for offset in offsets() {
+        for i in 0..4 {
+          sum[i] += data[offset + i] as u64;
+        }
+      }
+      let count = (rows.len() * cols.len()) as u64;
+      let mean = sum.map(|s| ((s + count / 2) / count) as u8);
+      for offset in offsets() {
+        data[offset..offset + 4].copy_from_slice(&mean);
+      }
+    }
+  }
+}
+
+// cut the buffer to a rounded rectangle: alpha outside the corner arcs goes to 0.
+// pixels the arc runs through keep the share of them it covers, from 4x4 samples, so the
+// edge is antialiased. the radius is capped at half the shorter side
+pub fn round_corners(data: &mut [u8], width: u32, height: u32, radius: u32) {
+  let radius = radius.min(width / 2).min(height / 2);
+  if radius == 0 {
+    return;
+  }
+  let r = radius as f32;
+  let (right, bottom) = ((width - radius) as f32, (height - radius) as f32);
+  let in_corner = |v: u32, len: u32| v < radius || v >= len - radius;
+
+  for y in (0..height).filter(|&y| in_corner(y, height)) {
+    for x in (0..width).filter(|&x| in_corner(x, width)) {
+      let mut inside = 0;
+      for sy in 0..4 {
+        for sx in 0..4 {
+          let px = x as f32 + (sx as f32 + 0.5) / 4.0;
+          let py = y as f32 + (sy as f32 + 0.5) / 4.0;
+          // nearest arc centre; along the straight edges the distance is 0
+          let dx = px - px.clamp(r, right);
+          let dy = py - py.clamp(r, bottom);
+          if dx * dx + dy * dy <= r * r {
+            inside += 1;
+          }
+        }
+      }
+      let alpha = &mut data[((y * width + x) * 4 + 3) as usize];
+      *alpha = ((*alpha as u32 * inside + 8) / 16) as u8;
+    }
+  }
+}
+
+// stretch each colour channel linearly so its darkest value lands on 0 and its brightest
+// on 255. `clip` percent of the pixels at each end are treated as outliers and clamp
+// instead of setting the range. fully transparent pixels don't count, flat channels stay
+pub fn auto_contrast(img: DynamicImage, clip: f32) -> DynamicImage {
+  let mut rgba = img.to_rgba8();
+  let mut histograms = [[0u64; 256]; 3];
+  for pixel in rgba.pixels().filter(|p| p[3] > 0) {
+    for i in 0..3 {
+      histograms[i][pixel[i] as usize] += 1;
+    }
+  }
+
+  let ranges = histograms.map(|histogram| {
+    let total: u64 = histogram.iter().sum();
+    let skip = (total as f64 * clip as f64 / 100.0) as u64;
+    (
+      clip_point(&histogram, skip, 0..256),
+      clip_point(&histogram, skip, (0..256).rev()),
+    )
+  });
+
+  for pixel in rgba.pixels_mut() {
+    for (i, &(low, high)) in ranges.iter().enumerate() {
+      if high > low {
+        let stretched = (pixel[i] as f32 - low as f32) * 255.0 / (high - low) as f32;
+        pixel[i] = stretched.round().clamp(0.0, 255.0) as u8;
+      }
+    }
+  }
+  DynamicImage::ImageRgba8(rgba)
+}
+
+// --edge-1/--edge-2: sobel gradient magnitude of the luma, clamped to 255, as opaque grey.
+// luma is taken over black so the outline of an alpha shape counts as an edge, and the
+// border repeats its outermost pixels. the result is always 8 bit
+pub fn sobel(img: DynamicImage) -> DynamicImage {
+  let rgba = img.to_rgba8();
+  let (width, height) = rgba.dimensions();
+  let luma: Vec<f32> = rgba
+    .pixels()
+    .map(|p| pixel_luma(&p.0) as f32 * p[3] as f32 / 255.0)
+    .collect();
+  let (last_x, last_y) = (width as i64 - 1, height as i64 - 1);
+  let at = |x: i64, y: i64| luma[(y.clamp(0, last_y) * width as i64 + x.clamp(0, last_x)) as usize];
+
+  let edges = RgbaImage::from_fn(width, height, |x, y| {
+    let (x, y) = (x as i64, y as i64);
+    let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+      - at(x - 1, y - 1)
+      - 2.0 * at(x - 1, y)
+      - at(x - 1, y + 1);
+    let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+      - at(x - 1, y - 1)
+      - 2.0 * at(x, y - 1)
+      - at(x + 1, y - 1);
+    let magnitude = (gx * gx + gy * gy).sqrt().round().min(255.0) as u8;
+    Rgba([magnitude, magnitude, magnitude, 255])
+  });
+  DynamicImage::ImageRgba8(edges)
+}
+
+// the first value, walking in `order`, once more than `skip` pixels have been passed
+fn clip_point(histogram: &[u64; 256], skip: u64, mut order: impl Iterator<Item = usize>) -> usize {
+  let mut seen = 0;
+  order
+    .find(|&v| {
+      seen += histogram[v];
+      seen > skip
+    })
+    .unwrap_or(0)
+}
+
+fn pixel_luma(p: &[u8]) -> u8 {
+  (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32).round() as u8
+}
+
+// pure black and white from rec. 601 luma: 255 above `t`, 0 at or below, one byte per
+// pixel. None picks `t` with otsu's method. alpha is ignored
+pub fn threshold(
+  data: &[u8],
+  width: u32,
+  height: u32,
+  t: Option<u8>,
+) -> Result<Vec<u8>, ImageDataErrors> {
+  if data.len() != width as usize * height as usize * 4 {
+    return Err(ImageDataErrors::MalformedBuffer(
+      data.len(),
+      (width, height),
+    ));
+  }
+  let luma: Vec<u8> = data.chunks_exact(4).map(pixel_luma).collect();
+  let t = t.unwrap_or_else(|| otsu(&luma));
+  Ok(luma.iter().map(|&l| if l > t { 255 } else { 0 }).collect())
+}
+
+// --sort-pixels rows|cols
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortAxis {
+  Rows,
+  Cols,
+}
+
+// glitch look: along every row (or column), each unbroken run of pixels whose luma is
+// within low..=high is sorted dark to light. pixels outside the band stay put and split
+// the runs. equal lumas keep their order, alpha moves with its pixel
+pub fn pixel_sort(data: &mut [u8], width: u32, height: u32, axis: SortAxis, low: u8, high: u8) {
+  let (width, height) = (width as usize, height as usize);
+  // (number of lines, pixels per line, step between pixels, step between lines)
+  let (lines, len, step, stride) = match axis {
+    SortAxis::Rows => (height, width, 1, width),
+    SortAxis::Cols => (width, height, width, 1),
+  };
+
+  let mut line: Vec<[u8; 4]> = Vec::with_capacity(len);
+  for l in 0..lines {
+    let offset = |i: usize| (l * stride + i * step) * 4;
+    line.clear();
+    line.extend((0..len).map(|i| {
+      let p = &data[offset(i)..offset(i) + 4];
+      [p[0], p[1], p[2], p[3]]
+    }));
+
+    let in_band = |p: &[u8; 4]| (low..=high).contains(&pixel_luma(p));
+    let mut start = 0;
+    while start < len {
+      if !in_band(&line[start]) {
+        start += 1;
+        continue;
+      }
+      let end = (start..len).find(|&i| !in_band(&line[i])).unwrap_or(len);
+      line[start..end].sort_by_key(|p| pixel_luma(p));
+      start = end;
+    }
+
+    for (i, p) in line.iter().enumerate() {
+      data[offset(i)..offset(i) + 4].copy_from_slice(p);
+    }
+  }
+}
+
+// the split that maximises the variance between the dark and the light class
+fn otsu(luma: &[u8]) -> u8 {
+  let mut histogram = [0u64; 256];
+  for &l in luma {
+    histogram[l as usize] += 1;
+  }
+  let total = luma.len() as f64;
+  let sum: f64 = (0..256).map(|v| v as f64 * histogram[v] as f64).sum();
+
+  let (mut weight_dark, mut sum_dark) = (0.0, 0.0);
+  let (mut best, mut best_variance) = (0, 0.0);
+  for (v, &count) in histogram.iter().enumerate() {
+    weight_dark += count as f64;
+    sum_dark += v as f64 * count as f64;
+    let weight_light = total - weight_dark;
+    if weight_dark == 0.0 || weight_light == 0.0 {
+      continue;
+    }
+    let mean_dark = sum_dark / weight_dark;
+    let mean_light = (sum - sum_dark) / weight_light;
+    let variance = weight_dark * weight_light * (mean_dark - mean_light).powi(2);
+    if variance > best_variance {
+      (best, best_variance) = (v as u8, variance);
+    }
+  }
+  best
+}
+
+// drop alpha: every pixel becomes fully opaque with its colour as is
+pub fn force_opaque(data: &mut [u8]) {
+  for pixel in data.chunks_exact_mut(4) {
+    pixel[3] = 255;
+  }
+}
+
+// composite over a solid background instead, so transparent areas take its colour.
+// the background's own alpha is ignored, the result is always opaque. this isn't a
+// blend, so it rounds to nearest regardless of --rounding
+pub fn flatten(data: &mut [u8], bg: Rgba<u8>) {
+  for pixel in data.chunks_exact_mut(4) {
+    let alpha = pixel[3] as f32 / 255.0;
+    for i in 0..3 {
+      pixel[i] = (pixel[i] as f32 * alpha + bg[i] as f32 * (1.0 - alpha)).round() as u8;
+    }
+    pixel[3] = 255;
+  }
+}
+
+// --drop-shadow "offset,blur,opacity,color"
+#[derive(Debug, Clone, Copy)]
+pub struct DropShadow {
+  pub offset: (i32, i32), // pixels right and down
+  pub blur: u32,          // box blur radius
+  pub opacity: f32,
+  pub color: Rgba<u8>,
+}
+
+// the top image's alpha shape moved by `offset`, blurred and filled with `color` at
+// `opacity` (times the colour's own alpha). anything shifted in from outside is transparent
+pub fn drop_shadow(
+  top_alpha: &GrayImage,
+  offset: (i32, i32),
+  blur: u32,
+  opacity: f32,
+  color: Rgba<u8>,
+) -> RgbaImage {
+  let (width, height) = top_alpha.dimensions();
+  let mut shape = vec![0u8; (width * height) as usize];
+  for (x, y, pixel) in top_alpha.enumerate_pixels() {
+    let (shifted_x, shifted_y) = (x as i64 + offset.0 as i64, y as i64 + offset.1 as i64);
+    if (0..width as i64).contains(&shifted_x) && (0..height as i64).contains(&shifted_y) {
+      shape[(shifted_y * width as i64 + shifted_x) as usize] = pixel[0];
+    }
+  }
+  box_blur(&mut shape, width, height, blur);
+
+  let strength = opacity * color[3] as f32 / 255.0;
+  RgbaImage::from_fn(width, height, |x, y| {
+    let alpha = shape[(y * width + x) as usize] as f32 * strength;
+    Rgba([color[0], color[1], color[2], alpha.round() as u8])
+  })
+}
+
+// mean over the (2 * radius + 1) square around every value of a single channel buffer,
+// as a horizontal then a vertical pass. windows are cut off at the edges
+pub fn box_blur(values: &mut [u8], width: u32, height: u32, radius: u32) {
+  let (width, height, radius) = (width as usize, height as usize, radius as usize);
+  blur_pass(values, width, height, radius, 1, width); // rows
+  blur_pass(values, height, width, radius, width, 1); // columns
+}
+
+// `lines` runs of `len` values; `step` apart within a run, runs start `stride` apart
+fn blur_pass(
+  values: &mut [u8],
+  len: usize,
+  lines: usize,
+  radius: usize,
+  step: usize,
+  stride: usize,
+) {
+  let mut prefix = vec![0u32; len + 1];
+  for line in 0..lines {
+    let start = line * stride;
+    for i in 0..len {
+      prefix[i + 1] = prefix[i] + values[start + i * step] as u32;
+    }
+    for i in 0..len {
+      let (low, high) = (i.saturating_sub(radius), (i + radius + 1).min(len));
+      let count = (high - low) as u32;
+      values[start + i * step] = ((prefix[high] - prefix[low] + count / 2) / count) as u8;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn posterize_two_levels_is_black_or_white() {
+    let mut data: Vec<u8> = (0..=255).flat_map(|v| [v, 255 - v, v / 2, 77]).collect();
+    posterize(&mut data, 2);
+    for pixel in data.chunks_exact(4) {
+      assert!(
+        pixel[..3].iter().all(|&c| c == 0 || c == 255),
+        "{:?}",
+        pixel
+      );
+      assert_eq!(pixel[3], 77);
+    }
+    // 127 and 128 sit either side of the middle
+    assert_eq!(&data[127 * 4..127 * 4 + 3], &[0, 255, 0]);
+    assert_eq!(&data[128 * 4..128 * 4 + 3], &[255, 0, 0]);
+  }
+
+  #[test]
+  fn pixelate_whole_image_block_is_its_mean() {
+    // 16 pixels, channel values 0..16 apart, so the means are known
+    let mut data: Vec<u8> = (0..16u8)
+      .flat_map(|i| [i * 10, 255 - i, 100, i * 16])
+      .collect();
+    pixelate(&mut data, 4, 4, 4);
+    // (0 + 150) / 2 = 75, (255 + 240) / 2 = 247.5, 100, (0 + 240) / 2 = 120; halves round up
+    for pixel in data.chunks_exact(4) {
+      assert_eq!(pixel, [75, 248, 100, 120]);
+    }
+  }
+
+  #[test]
+  fn pixelate_edge_blocks_are_smaller() {
+    // 3x1 with block 2: the first two pixels share a mean, the last is on its own
+    let mut data = vec![0, 0, 0, 255, 10, 10, 10, 255, 99, 99, 99, 255];
+    pixelate(&mut data, 3, 1, 2);
+    assert_eq!(data, [5, 5, 5, 255, 5, 5, 5, 255, 99, 99, 99, 255]);
+  }
+
+  #[test]
+  fn round_corners_clears_corners_only() {
+    let (width, height) = (10, 10);
+    let mut data: Vec<u8> = [30, 60, 90, 200].repeat(width * height);
+    round_corners(&mut data, width as u32, height as u32, 4);
+    let alpha = |x: usize, y: usize| data[(y * width + x) * 4 + 3];
+    for (x, y) in [(0, 0), (9, 0), (0, 9), (9, 9)] {
+      assert_eq!(alpha(x, y), 0, "corner {},{}", x, y);
+    }
+    // the middle and the straight edges keep their alpha, colour is never touched
+    for (x, y) in [(5, 5), (0, 5), (5, 0), (9, 4), (4, 9)] {
+      assert_eq!(alpha(x, y), 200, "{},{}", x, y);
+    }
+    // on the arc: partly covered
+    assert!((1..200).contains(&alpha(1, 1)));
+    assert!(data.chunks_exact(4).all(|p| p[..3] == [30, 60, 90]));
+  }
+
+  #[test]
+  fn auto_contrast_stretches_to_full_range() {
+    // red and green between 100 and 150, blue flat (and left as is), plus a transparent
+    // pixel that shouldn't count
+    let mut data: Vec<u8> = (100..=150).flat_map(|v| [v, 250 - v, 125, 255]).collect();
+    data.extend_from_slice(&[0, 255, 0, 0]);
+    let width = data.len() as u32 / 4;
+    let img = DynamicImage::ImageRgba8(RgbaImage::from_raw(width, 1, data).unwrap());
+    let stretched = auto_contrast(img, 0.0).to_rgba8();
+    let pixels: Vec<&Rgba<u8>> = stretched.pixels().take(51).collect();
+    for i in 0..2 {
+      assert_eq!(pixels.iter().map(|p| p[i]).min(), Some(0));
+      assert_eq!(pixels.iter().map(|p| p[i]).max(), Some(255));
+    }
+    assert_eq!(pixels[0].0, [0, 255, 125, 255]);
+    assert_eq!(pixels[25].0, [128, 128, 125, 255]);
+    assert_eq!(pixels[50].0, [255, 0, 125, 255]);
+  }
+
+  #[test]
+  fn threshold_splits_two_levels() {
+    // left half grey 50, right half grey 200, 8x2
+    let data: Vec<u8> = (0..16)
+      .flat_map(|i| {
+        let v = if i % 8 < 4 { 50 } else { 200 };
+        [v, v, v, 255]
+      })
+      .collect();
+    let expected: Vec<u8> = (0..16).map(|i| if i % 8 < 4 { 0 } else { 255 }).collect();
+    assert_eq!(threshold(&data, 8, 2, Some(128)).unwrap(), expected);
+    assert_eq!(threshold(&data, 8, 2, None).unwrap(), expected);
+    // any split between the levels is as good, otsu takes the first: the dark level itself
+    let luma: Vec<u8> = data.chunks_exact(4).map(pixel_luma).collect();
+    assert_eq!(otsu(&luma), 50);
+    // at or below the level is black
+    assert!(threshold(&data, 8, 2, Some(200))
+      .unwrap()
+      .iter()
+      .all(|&v| v == 0));
+  }
+
+  #[test]
+  fn threshold_splits_a_ramp() {
+    // one pixel of each grey 0..=255, 16x16
+    let data: Vec<u8> = (0..=255u8).flat_map(|v| [v, v, v, 255]).collect();
+    let first_white = |t| {
+      let bw = threshold(&data, 16, 16, t).unwrap();
+      bw.iter().position(|&v| v == 255).unwrap()
+    };
+    // an even histogram splits down the middle
+    assert_eq!(first_white(None), 128);
+    assert_eq!(first_white(Some(200)), 201);
+    assert!(matches!(
+      threshold(&data, 16, 15, None),
+      Err(ImageDataErrors::MalformedBuffer(1024, (16, 15)))
+    ));
+  }
+
+  #[test]
+  fn drop_shadow_sits_at_offset_with_opacity() {
+    // a 2x2 opaque square at (2, 2) on a 10x10 canvas
+    let shape = GrayImage::from_fn(10, 10, |x, y| {
+      image::Luma([if (2..4).contains(&x) && (2..4).contains(&y) {
+        255
+      } else {
+        0
+      }])
+    });
+    let color = Rgba([10, 20, 30, 255]);
+    let shadow = drop_shadow(&shape, (3, 4), 0, 0.5, color);
+    for (x, y, pixel) in shadow.enumerate_pixels() {
+      let under_shadow = (5..7).contains(&x) && (6..8).contains(&y);
+      assert_eq!(pixel[3], if under_shadow { 128 } else { 0 }, "{},{}", x, y);
+      assert_eq!(pixel.0[..3], [10, 20, 30]);
+    }
+
+    // the colour's own alpha scales the opacity too, and a negative offset moves up-left
+    let faint = drop_shadow(&shape, (-2, -2), 0, 1.0, Rgba([0, 0, 0, 51]));
+    assert_eq!(faint.get_pixel(0, 0)[3], 51);
+    assert_eq!(faint.get_pixel(2, 2)[3], 0);
+  }
+
+  #[test]
+  fn pixel_sort_sorts_runs_inside_the_band() {
+    let grey = |v: u8| [v, v, v, 255];
+    // 10 and 250 are outside 64..=192 and split the row into two runs
+    let row = [150, 100, 10, 180, 70, 120, 250, 90];
+    let mut data: Vec<u8> = row.iter().flat_map(|&v| grey(v)).collect();
+    pixel_sort(&mut data, 8, 1, SortAxis::Rows, 64, 192);
+    let sorted: Vec<u8> = data.chunks_exact(4).map(|p| p[0]).collect();
+    assert_eq!(sorted, [100, 150, 10, 70, 120, 180, 250, 90]);
+
+    // the same values down a column sort the same way, the other column is left alone
+    let mut data: Vec<u8> = row
+      .iter()
+      .flat_map(|&v| [grey(v), grey(200)].concat())
+      .collect();
+    pixel_sort(&mut data, 2, 8, SortAxis::Cols, 64, 192);
+    let column: Vec<u8> = data.chunks_exact(8).map(|p| p[0]).collect();
+    assert_eq!(column, [100, 150, 10, 70, 120, 180, 250, 90]);
+    assert!(data.chunks_exact(8).all(|p| p[4] == 200));
+  }
+
+  #[test]
+  fn sobel_responds_on_the_edge_only() {
+    // black left half, white right half
+    let img = RgbaImage::from_fn(6, 4, |x, _| match x < 3 {
+      true => Rgba([0, 0, 0, 255]),
+      false => Rgba([255, 255, 255, 255]),
+    });
+    let edges = sobel(DynamicImage::ImageRgba8(img)).to_rgba8();
+    for (x, _, pixel) in edges.enumerate_pixels() {
+      let expected = if x == 2 || x == 3 { 255 } else { 0 };
+      assert_eq!(pixel.0, [expected, expected, expected, 255], "x {}", x);
+    }
+  }
+}
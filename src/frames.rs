@@ -0,0 +1,58 @@
+// pick single frames out of animated inputs. only gif is animated in this image version
+use crate::ImageDataErrors;
+use image::{codecs::gif::GifDecoder, AnimationDecoder, DynamicImage, RgbaImage};
+use std::fs::File;
+use std::io::BufReader;
+
+fn open_gif(path: &str) -> Result<GifDecoder<BufReader<File>>, ImageDataErrors> {
+  let file = match File::open(path) {
+    Ok(file) => file,
+    Err(e) => return Err(ImageDataErrors::UnableToReadImageFromPath(e)),
+  };
+  GifDecoder::new(BufReader::new(file)).map_err(ImageDataErrors::UnableToDecodeImage)
+}
+
+// frames come back composited to the full canvas, so they all share one size
+pub fn decode_gif_frame(path: &str, index: usize) -> Result<DynamicImage, ImageDataErrors> {
+  let mut count = 0;
+  for frame in open_gif(path)?.into_frames() {
+    let frame = frame.map_err(ImageDataErrors::UnableToDecodeImage)?;
+    if count == index {
+      return Ok(DynamicImage::ImageRgba8(frame.into_buffer()));
+    }
+    count += 1;
+  }
+  Err(ImageDataErrors::FrameOutOfRange(index, count))
+}
+
+pub fn decode_gif_frames(path: &str) -> Result<Vec<RgbaImage>, ImageDataErrors> {
+  let frames = open_gif(path)?
+    .into_frames()
+    .collect_frames()
+    .map_err(ImageDataErrors::UnableToDecodeImage)?;
+  Ok(
+    frames
+      .into_iter()
+      .map(|frame| frame.into_buffer())
+      .collect(),
+  )
+}
+
+// per-channel mean over all frames, alpha included. frame delays are ignored,
+// every frame counts the same. frames have to share one size
+pub fn average_frames(frames: Vec<RgbaImage>) -> RgbaImage {
+  let (width, height) = frames[0].dimensions();
+  let mut sum = vec![0u64; (width * height * 4) as usize];
+  for frame in &frames {
+    for (total, &value) in sum.iter_mut().zip(frame.as_raw()) {
+      *total += value as u64;
+    }
+  }
+
+  let count = frames.len() as u64;
+  let data = sum
+    .iter()
+    .map(|total| ((total + count / 2) / count) as u8)
+    .collect();
+  RgbaImage::from_raw(width, height, data).unwrap()
+}
@@ -0,0 +1,168 @@
+// contact sheets: any number of inputs laid out in a cols x rows grid on one canvas
+use crate::args::Args;
+use crate::pair::SaveOptions;
+use crate::{
+  apply_post_filters, check_alpha_output, check_color_space, check_color_type,
+  find_image_from_path, get_smallest_dimensions, log_run, output_format, pinned_color_type,
+  resize_filter, write_output, FloatingImage, ImageDataErrors,
+};
+use image::{imageops, imageops::FilterType, DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+// the canvas for cols x rows cells with gutters only between them. None when it
+// wouldn't fit one rgba buffer, the same limit --benchmark has
+pub fn canvas_size(cols: u32, rows: u32, cell: (u32, u32), gutter: u32) -> Option<(u32, u32)> {
+  let side = |count: u32, cell: u32| {
+    count
+      .checked_mul(cell)?
+      .checked_add(count.saturating_sub(1).checked_mul(gutter)?)
+  };
+  let (width, height) = (side(cols, cell.0)?, side(rows, cell.1)?);
+  width.checked_mul(height)?.checked_mul(4)?;
+  Some((width, height))
+}
+
+pub fn too_large(cols: u32, rows: u32, cell: (u32, u32), gutter: u32) -> ImageDataErrors {
+  ImageDataErrors::InvalidArgument(
+    String::from("--grid"),
+    format!(
+      "{}x{} cells of {}x{} with a {}px gutter are too large for one rgba buffer",
+      cols, rows, cell.0, cell.1, gutter
+    ),
+  )
+}
+
+// images fill the grid row by row; cells past the last image stay background.
+// every image is stretched to exactly `cell` and drawn over the background,
+// gutters only go between cells
+pub fn build_grid(
+  images: &[DynamicImage],
+  cols: u32,
+  rows: u32,
+  cell: (u32, u32),
+  gutter: u32,
+  bg: Rgba<u8>,
+  filter: FilterType,
+) -> Result<DynamicImage, ImageDataErrors> {
+  let (cell_width, cell_height) = cell;
+  let (width, height) =
+    canvas_size(cols, rows, cell, gutter).ok_or_else(|| too_large(cols, rows, cell, gutter))?;
+  let mut canvas = RgbaImage::from_pixel(width, height, bg);
+
+  // every cell's corner is inside the canvas, so these offsets can't overflow
+  for (i, image) in images
+    .iter()
+    .take(cols as usize * rows as usize)
+    .enumerate()
+  {
+    let (col, row) = (i as u32 % cols, i as u32 / cols);
+    let resized = image.resize_exact(cell_width, cell_height, filter);
+    imageops::overlay(
+      &mut canvas,
+      &resized.to_rgba8(),
+      col * (cell_width + gutter),
+      row * (cell_height + gutter),
+    );
+  }
+  Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+// read every --inputs path, lay them out and save to the output path
+pub fn run_grid(args: &Args, cols: u32, rows: u32) -> Result<(), ImageDataErrors> {
+  let capacity = cols as usize * rows as usize;
+  let paths = match args.inputs.len() > capacity {
+    true if !args.grid_truncate => {
+      return Err(ImageDataErrors::TooManyGridImages(
+        args.inputs.len(),
+        capacity,
+      ));
+    }
+    true => &args.inputs[..capacity],
+    false => &args.inputs[..],
+  };
+
+  let mut images = Vec::new();
+  for path in paths {
+    if !args.assume_srgb {
+      check_color_space(path)?;
+    }
+    images.push(find_image_from_path(path.clone())?.0);
+  }
+
+  // default cell: the smallest input, so nothing gets scaled up
+  let cell = match args.grid_cell {
+    Some(cell) => cell,
+    None => images
+      .iter()
+      .map(|image| image.dimensions())
+      .reduce(get_smallest_dimensions)
+      .unwrap(),
+  };
+  let grid = build_grid(
+    &images,
+    cols,
+    rows,
+    cell,
+    args.gutter,
+    args.grid_bg,
+    resize_filter(args),
+  )?;
+  println!("width: {}, height: {}\n", grid.width(), grid.height());
+
+  // the inputs can all differ, so there is no image format to fall back on
+  let format = output_format(args, None)?;
+  check_alpha_output(args, format)?;
+  check_color_type(args, format)?;
+  let mut output = FloatingImage::new(grid.width(), grid.height(), args.output.clone());
+  output.set_data(grid.to_rgba8().into_raw())?;
+  apply_post_filters(&mut output, args, format)?;
+
+  let opts = SaveOptions {
+    format,
+    bit_depth: 8,
+    exif_source: None,
+    sources: match args.embed_sources {
+      true => paths.iter().map(String::as_str).collect(),
+      false => Vec::new(),
+    },
+    quantize: args.quantize,
+    quality: args.quality,
+    verify: args.verify,
+    color_type: pinned_color_type(args),
+  };
+  write_output(&output, &args.output, format, &opts)?;
+
+  let inputs = paths
+    .iter()
+    .map(String::as_str)
+    .zip(images.iter().map(|image| image.dimensions()))
+    .collect();
+  log_run(args, inputs, &output, &opts)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn canvas_counts_gutters_between_cells_only() {
+    assert_eq!(canvas_size(3, 2, (10, 20), 4), Some((38, 44)));
+    assert_eq!(canvas_size(1, 1, (10, 20), 4), Some((10, 20)));
+  }
+
+  #[test]
+  fn overflowing_canvas_is_an_error() {
+    let image = DynamicImage::ImageRgba8(RgbaImage::new(2, 2));
+    let white = Rgba([255, 255, 255, 255]);
+    for (cols, rows, cell, gutter) in [
+      (70000, 70000, (691, 688), 0),
+      (1, 2, (1, u32::MAX), 0),
+      (2, 1, (1, 1), u32::MAX),
+      (40000, 40000, (1, 1), 0),
+    ] {
+      assert!(matches!(
+        build_grid(std::slice::from_ref(&image), cols, rows, cell, gutter, white, FilterType::Nearest),
+        Err(ImageDataErrors::InvalidArgument(flag, _)) if flag == "--grid"
+      ));
+    }
+  }
+}
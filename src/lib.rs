@@ -0,0 +1,2 @@
+// reusable pieces of the combiner, so other crates can bring their own blend modes
+pub mod blend;
@@ -1,19 +1,164 @@
+#[cfg(feature = "auto-align")]
+mod align;
 mod args; // declare as module
+mod batch;
+mod benchmark;
+mod colorspace;
+mod compare;
+mod exif;
+mod filters;
+mod font;
+mod frames;
+mod grid;
+mod mask;
+mod pair;
+mod pngtext;
+mod quantize;
+mod rng;
+mod runlog;
+mod stats;
+mod streaming;
+mod tile;
 use args::Args;
+use combiner::blend::{self, Blender};
+use image::codecs::jpeg::JpegEncoder;
+use image::error::{EncodingError, ImageFormatHint};
 use image::{
-  imageops::FilterType::Triangle, io::Reader, DynamicImage, GenericImageView, ImageError,
-  ImageFormat,
+  imageops::FilterType, io::Reader, ColorType, DynamicImage, GenericImageView, ImageBuffer,
+  ImageError, ImageFormat,
 };
+use pair::{ImagePair, Jitter, SaveOptions, SizePolicy};
 use std::convert::TryInto;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use tile::Anchor;
 
 #[derive(Debug)]
 enum ImageDataErrors {
   DifferentImageFormats,
   BufferTooSmall,
+  MalformedBuffer(usize, (u32, u32)), // buffer length, the size it should hold
   UnableToReadImageFromPath(std::io::Error),
   UnableToFormatImage(String),
   UnableToDecodeImage(ImageError),
   UnableToSaveImage(ImageError),
+  UnsupportedBitDepth(u8, ImageFormat),
+  UnsupportedColorType(ColorType, ImageFormat),
+  NoAlphaChannel(String, String), // what drops the alpha, the flag that needs it
+  UnableToReadManifest(std::io::Error),
+  InvalidManifestLine(String),
+  BatchFailed(usize), // number of failed jobs
+  TimedOut(u64),      // the per-image limit in seconds
+  NonIntegerScale((u32, u32), (u32, u32)),
+  UnableToWriteImageToPath(std::io::Error),
+  UnsupportedColorSpace(String),
+  FrameOutOfRange(usize, usize),   // requested frame, frame count
+  TooManyGridImages(usize, usize), // inputs, grid cells
+  VerificationFailed(String),
+  UnableToWriteLog(std::io::Error),
+  MemoryLimitExceeded(u64, u64),        // estimate, limit, both in MB
+  ColorProfileMismatch(String, String), // the two input paths
+  MissingArgument(String),
+  InvalidArgument(String, String), // flag, what is wrong with its value
+  ConflictingArguments(String, String), // two options that can't be used together
+  UnreadableInput(String, std::io::Error),
+}
+
+impl std::fmt::Display for ImageDataErrors {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      ImageDataErrors::DifferentImageFormats => write!(f, "the two inputs have different formats"),
+      ImageDataErrors::BufferTooSmall => write!(f, "combined data doesn't fit the output buffer"),
+      ImageDataErrors::MalformedBuffer(length, (width, height)) => {
+        write!(
+          f,
+          "a pixel buffer of {} bytes doesn't hold {}x{} rgba pixels",
+          length, width, height
+        )
+      }
+      ImageDataErrors::UnableToReadImageFromPath(e) => write!(f, "can't read image: {}", e),
+      ImageDataErrors::UnableToFormatImage(path) => {
+        write!(f, "can't tell the image format of {}", path)
+      }
+      ImageDataErrors::UnableToDecodeImage(e) => write!(f, "can't decode image: {}", e),
+      ImageDataErrors::UnableToSaveImage(e) => write!(f, "can't save image: {}", e),
+      ImageDataErrors::UnsupportedBitDepth(depth, format) => {
+        write!(f, "{:?} output can't be saved at {} bit", format, depth)
+      }
+      ImageDataErrors::UnsupportedColorType(color_type, format) => {
+        write!(f, "{:?} output can't be saved as {:?}", format, color_type)
+      }
+      ImageDataErrors::NoAlphaChannel(what, flag) => write!(
+        f,
+        "{} output has no alpha for {}; pick another format{}",
+        what,
+        flag,
+        match flag.as_str() {
+          "--round-corners" => " or fill with --bg-color",
+          _ => "",
+        }
+      ),
+      ImageDataErrors::UnableToReadManifest(e) => write!(f, "can't read manifest: {}", e),
+      ImageDataErrors::InvalidManifestLine(line) => {
+        write!(f, "manifest line needs image_1 image_2 output: {}", line)
+      }
+      ImageDataErrors::BatchFailed(failed) => write!(f, "{} batch job(s) failed", failed),
+      ImageDataErrors::TimedOut(seconds) => write!(f, "combine took longer than {}s", seconds),
+      ImageDataErrors::NonIntegerScale(larger, smaller) => write!(
+        f,
+        "{}x{} is not a whole multiple of {}x{}",
+        larger.0, larger.1, smaller.0, smaller.1
+      ),
+      ImageDataErrors::UnableToWriteImageToPath(e) => write!(f, "can't write output: {}", e),
+      ImageDataErrors::UnsupportedColorSpace(space) => {
+        write!(
+          f,
+          "{} images aren't supported (--assume-srgb to force)",
+          space
+        )
+      }
+      ImageDataErrors::FrameOutOfRange(frame, count) => {
+        write!(f, "frame {} requested, the image has {}", frame, count)
+      }
+      ImageDataErrors::TooManyGridImages(inputs, cells) => write!(
+        f,
+        "{} inputs don't fit {} grid cells (--grid-truncate drops the rest)",
+        inputs, cells
+      ),
+      ImageDataErrors::VerificationFailed(reason) => write!(f, "verification failed: {}", reason),
+      ImageDataErrors::UnableToWriteLog(e) => write!(f, "can't write command log: {}", e),
+      ImageDataErrors::MemoryLimitExceeded(estimate, limit) => {
+        write!(
+          f,
+          "job needs about {} MB, over the {} MB limit",
+          estimate, limit
+        )
+      }
+      ImageDataErrors::ColorProfileMismatch(path_1, path_2) => {
+        write!(f, "{} and {} have different icc profiles", path_1, path_2)
+      }
+      ImageDataErrors::MissingArgument(what) => write!(f, "missing {}", what),
+      ImageDataErrors::InvalidArgument(flag, problem) => write!(f, "{}: {}", flag, problem),
+      ImageDataErrors::ConflictingArguments(flag_1, flag_2) => {
+        write!(f, "{} can't be used together with {}", flag_1, flag_2)
+      }
+      ImageDataErrors::UnreadableInput(path, e) => write!(f, "can't open {}: {}", path, e),
+    }
+  }
+}
+
+impl ImageDataErrors {
+  // 2 for a bad command line, 1 for anything that went wrong while running
+  fn exit_code(&self) -> i32 {
+    match self {
+      ImageDataErrors::MissingArgument(_)
+      | ImageDataErrors::InvalidArgument(..)
+      | ImageDataErrors::ConflictingArguments(..)
+      | ImageDataErrors::UnreadableInput(..) => 2,
+      _ => 1,
+    }
+  }
 }
 
 // holds metadata of image
@@ -49,36 +194,645 @@ impl FloatingImage {
   }
 }
 
-fn main() -> Result<(), ImageDataErrors> {
-  let args: Args = Args::new();
-  let (image_1, image_format_1): (DynamicImage, ImageFormat) = find_image_from_path(args.image_1)?;
-  let (image_2, image_format_2): (DynamicImage, ImageFormat) = find_image_from_path(args.image_2)?;
+fn main() {
+  if let Err(e) = run() {
+    eprintln!("error: {}", e);
+    std::process::exit(e.exit_code());
+  }
+}
 
-  if image_format_1 != image_format_2 {
-    return Err(ImageDataErrors::DifferentImageFormats);
+fn run() -> Result<(), ImageDataErrors> {
+  let args: Args = Args::try_new()?;
+
+  if let Some((width, height)) = args.benchmark {
+    return benchmark::run_benchmark(&args, width, height);
+  }
+  if let Some((cols, rows)) = args.grid {
+    return grid::run_grid(&args, cols, rows);
+  }
+
+  let run_one = match args.validate_only {
+    true => validate,
+    false => process,
+  };
+  match &args.manifest {
+    Some(manifest) => batch::run_batch(&args, batch::read_manifest(manifest)?, run_one),
+    None => run_one(&args),
+  }
+}
+
+// --validate-only: open the pair exactly as a full run would, with its colour space,
+// profile, memory and frame checks, then stop before anything is resized or combined
+fn validate(args: &Args) -> Result<(), ImageDataErrors> {
+  let pair = ImagePair::open(args)?;
+  if args.verbose {
+    let mut inputs = vec![(args.image_1.as_str(), &pair.img1, pair.format1)];
+    if args.mirror.is_none() {
+      inputs.push((args.image_2.as_str(), &pair.img2, pair.format2));
+    }
+    for (path, image, format) in inputs {
+      let (width, height) = image.dimensions();
+      println!(
+        "{}: ok, {:?} {}x{} {:?}",
+        path,
+        format,
+        width,
+        height,
+        image.color()
+      );
+    }
+  }
+  Ok(())
+}
+
+// one full combine: read both inputs, blend, save
+fn process(args: &Args) -> Result<(), ImageDataErrors> {
+  let pair = ImagePair::open(args)?;
+  let input_dimensions = (pair.img1.dimensions(), pair.img2.dimensions());
+  let format = output_format(args, Some(pair.format1))?;
+  check_alpha_output(args, format)?;
+  check_color_type(args, format)?;
+  let bit_depth = pair.output_bit_depth(args.output_bitdepth, format)?;
+
+  if args.stats {
+    let stats_1 = stats::channel_stats(pair.img1.to_rgba8().as_raw());
+    let stats_2 = stats::channel_stats(pair.img2.to_rgba8().as_raw());
+    stats::print_stats(&args.image_1, &stats_1);
+    let label_2 = match args.mirror {
+      Some(mirror) => format!("{} ({:?} mirror)", args.image_1, mirror),
+      None => args.image_2.clone(),
+    };
+    stats::print_stats(&label_2, &stats_2);
   }
 
-  let (image_1, image_2): (DynamicImage, DynamicImage) = standardize_size(image_1, image_2);
-  let mut output: FloatingImage =
-    FloatingImage::new(image_1.width(), image_1.height(), args.output);
+  let pair = match args.auto_contrast {
+    Some(clip) => pair.auto_contrast(clip),
+    None => pair,
+  };
+  let pair = pair.edge_detect(args.edge_1, args.edge_2);
+  let pair = match args.background_tile {
+    true => pair.tile_background(args),
+    false => pair.standardize(args)?,
+  };
+  let pair = match args.max_dimension {
+    Some(max_dimension) => pair.fit_within(max_dimension, resize_filter(args)),
+    None => pair,
+  };
+  // the size that gets written: a compare sheet is always full size, anything else
+  // is shrunk by --preview-scale
+  let (width, height) = match args.compare {
+    true => compare::sheet_dimensions(pair.dimensions(), args.divider_width),
+    false => {
+      let (width, height) = pair.dimensions();
+      preview_dimensions(width, height, args.preview_scale)
+    }
+  };
+  if args.compare && args.preview_scale > 1 {
+    println!("--preview-scale doesn't change --compare, ignoring it");
+  }
+  println!("width: {}, height: {}\n", width, height);
+  let pair = match args.lead_sharper {
+    true if args.blend_mode != blend::BlendMode::Alternate => {
+      println!("--lead-sharper only changes --blend-mode alternate, ignoring it");
+      pair
+    }
+    true => pair.lead_with_sharper(args.verbose),
+    false => pair,
+  };
+  if args.jitter.amount > 0 && args.blend_mode != blend::BlendMode::Alternate {
+    println!("--jitter only changes --blend-mode alternate, ignoring it");
+  }
+  if !args.background_tile && (args.anchor != Anchor::Center || args.canvas.is_some()) {
+    println!("--anchor and --canvas only change --background-tile, ignoring them");
+  }
+  #[cfg(feature = "auto-align")]
+  let pair = match args.auto_align {
+    true => pair.align(),
+    false => pair,
+  };
+
+  let mut output: FloatingImage = pair.combine(args.blend_mode, args)?;
+  if let Some(path) = &args.alpha_from {
+    let alpha = mask::load_mask(path, output.width, output.height, resize_filter(args))?;
+    mask::apply_alpha_mask(&mut output.data, &alpha);
+  }
+  if let Some(prefix) = &args.split_output {
+    // a mask or compare sheet replaces the alternate pattern, so there's nothing to split
+    let alternating = args.blend_mode == blend::BlendMode::Alternate
+      && !args.compare
+      && args.mask.is_none()
+      && !args.mask_from_diff;
+    match alternating {
+      true => save_split(&output, prefix, args)?,
+      false => println!("--split-output only works with --blend-mode alternate, ignoring it"),
+    }
+  }
+  apply_post_filters(&mut output, args, format)?;
+
+  if args.stats {
+    stats::print_stats(&output.name, &stats::channel_stats(&output.data));
+  }
+
+  let opts = SaveOptions {
+    format,
+    bit_depth,
+    exif_source: args.exif_preserve.then_some(args.image_1.as_str()),
+    sources: match (args.embed_sources, args.mirror) {
+      (false, _) => Vec::new(),
+      // image 2 is image 1 flipped, there is only one file
+      (true, Some(_)) => vec![args.image_1.as_str()],
+      (true, None) => vec![args.image_1.as_str(), args.image_2.as_str()],
+    },
+    quantize: args.quantize,
+    quality: args.quality,
+    verify: args.verify,
+    color_type: pinned_color_type(args),
+  };
+  pair.save(&output, &args.output, &opts)?;
+
+  // with --mirror image 2 is image 1 flipped, there is no second file
+  let mut inputs = vec![(args.image_1.as_str(), input_dimensions.0)];
+  if args.mirror.is_none() {
+    inputs.push((args.image_2.as_str(), input_dimensions.1));
+  }
+  log_run(args, inputs, &output, &opts)
+}
+
+// --log-command, once the output is saved
+fn log_run(
+  args: &Args,
+  inputs: Vec<(&str, (u32, u32))>,
+  output: &FloatingImage,
+  opts: &SaveOptions,
+) -> Result<(), ImageDataErrors> {
+  let log = match &args.log_command {
+    Some(log) => log,
+    None => return Ok(()),
+  };
+  let record = runlog::RunRecord {
+    inputs,
+    output: (output.width, output.height),
+    format: opts.format,
+    color_type: opts.quantize.is_none().then(|| saved_color_type(opts)),
+  };
+  runlog::append(log, args, &record)
+}
+
+// --color-type, or l8 for a thresholded output
+fn pinned_color_type(args: &Args) -> Option<ColorType> {
+  match (args.color_type, args.threshold) {
+    (Some(color_type), _) => Some(color_type),
+    (None, Some(_)) => Some(ColorType::L8),
+    (None, None) => None,
+  }
+}
+
+fn check_color_type(args: &Args, format: ImageFormat) -> Result<(), ImageDataErrors> {
+  match args.color_type {
+    Some(color_type) if !supports_color_type(format, color_type) => {
+      Err(ImageDataErrors::UnsupportedColorType(color_type, format))
+    }
+    _ => Ok(()),
+  }
+}
+
+// rounded corners and --alpha-from have to survive the save. without alpha the corners
+// need a colour to fill with, a replaced alpha channel is just lost.
+// thresholded output is grey only, so it never has alpha either
+fn check_alpha_output(args: &Args, format: ImageFormat) -> Result<(), ImageDataErrors> {
+  if keeps_alpha(args, format) {
+    return Ok(());
+  }
+  let what = match (args.threshold, args.color_type) {
+    (Some(_), _) => String::from("--threshold"),
+    (None, Some(color_type)) if !color_type.has_alpha() => format!("{:?}", color_type),
+    _ => format!("{:?}", format),
+  };
+  let no_alpha = |flag: &str| {
+    Err(ImageDataErrors::NoAlphaChannel(
+      what.clone(),
+      flag.to_string(),
+    ))
+  };
+  if args.alpha_from.is_some() {
+    return no_alpha("--alpha-from");
+  }
+  if args.round_corners.is_some() && args.bg_color.is_none() {
+    return no_alpha("--round-corners");
+  }
+  Ok(())
+}
+
+fn keeps_alpha(args: &Args, format: ImageFormat) -> bool {
+  supports_alpha(format)
+    && args.threshold.is_none()
+    && args
+      .color_type
+      .is_none_or(|color_type| color_type.has_alpha())
+}
+
+// effects on the finished buffer, in a fixed order
+fn apply_post_filters(
+  output: &mut FloatingImage,
+  args: &Args,
+  format: ImageFormat,
+) -> Result<(), ImageDataErrors> {
+  if let Some(axis) = args.sort_pixels {
+    let (width, height) = (output.width, output.height);
+    filters::pixel_sort(
+      &mut output.data,
+      width,
+      height,
+      axis,
+      args.sort_low,
+      args.sort_high,
+    );
+  }
+  if let Some(block) = args.pixelate {
+    filters::pixelate(&mut output.data, output.width, output.height, block);
+  }
+  if let Some(levels) = args.posterize {
+    filters::posterize(&mut output.data, levels);
+  }
+  if let Some(radius) = args.round_corners {
+    filters::round_corners(&mut output.data, output.width, output.height, radius);
+  }
+  // an output without alpha would show whatever colour is left in the cut corners
+  if args.strip_alpha || (args.round_corners.is_some() && !keeps_alpha(args, format)) {
+    match args.bg_color {
+      Some(bg) => filters::flatten(&mut output.data, bg),
+      None => filters::force_opaque(&mut output.data),
+    }
+  }
+  // stored as opaque grey rgba so stats and --verify still see the usual buffer;
+  // the save pins l8 unless --color-type says otherwise
+  if let Some(t) = args.threshold {
+    let bw = filters::threshold(&output.data, output.width, output.height, t)?;
+    output.data = bw.iter().flat_map(|&v| [v, v, v, 255]).collect();
+  }
+  Ok(())
+}
 
-  let combined_data: Vec<u8> = combine_images(image_1, image_2);
-  output.set_data(combined_data)?;
+fn format_from_extension(path: &str) -> Option<ImageFormat> {
+  Path::new(path)
+    .extension()
+    .and_then(ImageFormat::from_extension)
+}
 
-  if let Err(e) = image::save_buffer_with_format(
-    output.name,
-    &output.data,
-    output.width,
-    output.height,
-    image::ColorType::Rgba8,
-    image_format_1,
+// --output-format, then the output's extension, then --preset, then `fallback` (image 1's format)
+fn output_format(
+  args: &Args,
+  fallback: Option<ImageFormat>,
+) -> Result<ImageFormat, ImageDataErrors> {
+  let (format, source) = match (
+    args.output_format,
+    format_from_extension(&args.output),
+    args.preset,
+    fallback,
   ) {
-    Err(ImageDataErrors::UnableToSaveImage(e))
-  } else {
-    Ok(())
+    (Some(format), _, _, _) => (format, "--output-format"),
+    (None, Some(format), _, _) => (format, "output extension"),
+    (None, None, Some(preset), _) => (preset.format(), "--preset"),
+    (None, None, None, Some(format)) => (format, "image 1"),
+    (None, None, None, None) => {
+      return Err(ImageDataErrors::UnableToFormatImage(args.output.clone()))
+    }
+  };
+  if args.verbose {
+    println!("output format: {:?} (from {})", format, source);
+  }
+  Ok(format)
+}
+
+// hidden sibling of the output, so the final rename stays on the same filesystem.
+// the extension is kept because some encoders (pnm) pick their subtype from it
+fn temp_path_for(output: &str) -> PathBuf {
+  let path = Path::new(output);
+  let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+  path.with_file_name(format!(".tmp-{}-{}", std::process::id(), file_name))
+}
+
+// encode into a temp file and rename it over `path`, so an interrupted save
+// never leaves a half-written file behind (an existing output is still overwritten)
+fn write_output(
+  output: &FloatingImage,
+  path: &str,
+  format: ImageFormat,
+  opts: &SaveOptions,
+) -> Result<(), ImageDataErrors> {
+  let temp_path = temp_path_for(path);
+
+  let saved = match opts.quantize {
+    Some(colors) => quantize::save_quantized(output, &temp_path, format, colors),
+    None => save_image(
+      output,
+      &temp_path,
+      format,
+      saved_color_type(opts),
+      opts.quality,
+      streaming::STREAM_PIXELS,
+    ),
+  };
+  let mut result = saved.map_err(ImageDataErrors::UnableToSaveImage);
+  if let (true, Some(source)) = (result.is_ok(), opts.exif_source) {
+    result = preserve_exif(source, &temp_path, format);
+  }
+  if result.is_ok() && !opts.sources.is_empty() {
+    result = embed_sources(&opts.sources, &temp_path, format);
+  }
+  // checked before the rename, so a bad file never replaces the output
+  if result.is_ok() && opts.verify {
+    result = verify_output(output, &temp_path, format, opts);
+  }
+  if result.is_ok() {
+    result = std::fs::rename(&temp_path, path).map_err(ImageDataErrors::UnableToWriteImageToPath);
+  }
+
+  if result.is_err() {
+    let _ = std::fs::remove_file(&temp_path);
+  }
+  result
+}
+
+// formats that give back exactly the rgba8 buffer they were handed
+fn is_lossless(format: ImageFormat) -> bool {
+  matches!(
+    format,
+    ImageFormat::Png
+      | ImageFormat::Tiff
+      | ImageFormat::Bmp
+      | ImageFormat::Tga
+      | ImageFormat::Farbfeld
+  )
+}
+
+// decode the written file again. dimensions always have to match; pixels only
+// for lossless formats, and not after --quantize, which changes them on purpose
+fn verify_output(
+  output: &FloatingImage,
+  path: &Path,
+  format: ImageFormat,
+  opts: &SaveOptions,
+) -> Result<(), ImageDataErrors> {
+  // the format is known, and the output may have no extension to guess it from
+  let mut reader = Reader::open(path).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+  reader.set_format(format);
+  let written = reader
+    .decode()
+    .map_err(ImageDataErrors::UnableToDecodeImage)?;
+
+  if written.dimensions() != (output.width, output.height) {
+    return Err(ImageDataErrors::VerificationFailed(format!(
+      "wrote {}x{}, read back {}x{}",
+      output.width,
+      output.height,
+      written.width(),
+      written.height()
+    )));
+  }
+  if !is_lossless(format) || opts.quantize.is_some() {
+    return Ok(());
+  }
+  // what the buffer looks like after going through the saved color type and back
+  let expected = match saved_color_type(opts) {
+    ColorType::Rgba8 | ColorType::Rgba16 => output.data.clone(),
+    color_type => {
+      let dimensions = (output.width, output.height);
+      let saved = convert_color_type(&output.data, ColorType::Rgba8, color_type, dimensions);
+      convert_color_type(&saved, color_type, ColorType::Rgba8, dimensions)
+    }
+  };
+  if written.to_rgba8().as_raw() != &expected {
+    return Err(ImageDataErrors::VerificationFailed(String::from(
+      "pixels read back differ from the combined image",
+    )));
+  }
+  Ok(())
+}
+
+// --embed-sources: Source1, Source2, ... text chunks with the input paths as given
+fn embed_sources(
+  sources: &[&str],
+  output: &Path,
+  format: ImageFormat,
+) -> Result<(), ImageDataErrors> {
+  if format != ImageFormat::Png {
+    println!(
+      "--embed-sources: {:?} output can't hold text metadata, skipping",
+      format
+    );
+    return Ok(());
+  }
+
+  let entries: Vec<(String, String)> = sources
+    .iter()
+    .enumerate()
+    .map(|(i, source)| (format!("Source{}", i + 1), source.to_string()))
+    .collect();
+  let encoded = match std::fs::read(output) {
+    Ok(data) => data,
+    Err(e) => return Err(ImageDataErrors::UnableToReadImageFromPath(e)),
+  };
+  if let Some(with_text) = pngtext::embed_text(&encoded, &entries) {
+    if let Err(e) = std::fs::write(output, with_text) {
+      return Err(ImageDataErrors::UnableToWriteImageToPath(e));
+    }
+  }
+  Ok(())
+}
+
+// metadata is dropped on save, so splice image 1's exif back into the written file
+fn preserve_exif(source: &str, output: &Path, format: ImageFormat) -> Result<(), ImageDataErrors> {
+  if format != ImageFormat::Jpeg {
+    println!(
+      "--exif-preserve: {:?} output can't hold exif, skipping",
+      format
+    );
+    return Ok(());
+  }
+
+  let source_data = match std::fs::read(source) {
+    Ok(data) => data,
+    Err(e) => return Err(ImageDataErrors::UnableToReadImageFromPath(e)),
+  };
+  let exif = match exif::extract_exif(&source_data) {
+    Some(exif) => exif,
+    None => {
+      println!("--exif-preserve: {} has no exif, skipping", source);
+      return Ok(());
+    }
+  };
+
+  let encoded = match std::fs::read(output) {
+    Ok(data) => data,
+    Err(e) => return Err(ImageDataErrors::UnableToReadImageFromPath(e)),
+  };
+  if let Some(with_exif) = exif::embed_exif(&encoded, &exif) {
+    if let Err(e) = std::fs::write(output, with_exif) {
+      return Err(ImageDataErrors::UnableToWriteImageToPath(e));
+    }
+  }
+  Ok(())
+}
+
+fn supports_16_bit(format: ImageFormat) -> bool {
+  matches!(format, ImageFormat::Png | ImageFormat::Tiff)
+}
+
+fn supports_alpha(format: ImageFormat) -> bool {
+  !matches!(
+    format,
+    ImageFormat::Jpeg | ImageFormat::Pnm | ImageFormat::Hdr | ImageFormat::Dds
+  )
+}
+
+fn input_bit_depth(image: &DynamicImage) -> u8 {
+  let color = image.color();
+  (color.bytes_per_pixel() / color.channel_count()) * 8
+}
+
+// which color types each encoder can write; the rest only get the default rgba8
+fn supports_color_type(format: ImageFormat, color_type: ColorType) -> bool {
+  use ColorType::*;
+  match format {
+    ImageFormat::Png => matches!(
+      color_type,
+      L8 | La8 | Rgb8 | Rgba8 | L16 | La16 | Rgb16 | Rgba16
+    ),
+    ImageFormat::Tiff => matches!(color_type, L8 | Rgb8 | Rgba8 | L16 | Rgb16 | Rgba16),
+    ImageFormat::Tga => matches!(color_type, L8 | La8 | Rgb8 | Rgba8),
+    ImageFormat::Bmp => matches!(color_type, L8 | Rgb8 | Rgba8), // la8 loses its alpha
+    ImageFormat::Pnm => matches!(color_type, L8 | Rgb8 | L16 | Rgb16),
+    ImageFormat::Jpeg => matches!(color_type, L8 | Rgb8),
+    ImageFormat::Gif => matches!(color_type, Rgb8 | Rgba8),
+    ImageFormat::Farbfeld => color_type == Rgba16,
+    _ => color_type == Rgba8,
+  }
+}
+
+// repack a buffer as another color type: alpha is dropped or made opaque, colour goes
+// to luma, 8 bit channels are stretched onto 16 (v * 257, so 255 stays full scale).
+// 16 bit buffers are native endian bytes. png is big endian on disk: image's write_image
+// swaps native to big endian itself and streaming::save_png does the same by hand.
+// v * 257 has equal high and low bytes, so a wrong byte order wouldn't show in a round
+// trip; keep the buffer native and leave the swapping to the writers
+fn convert_color_type(
+  data: &[u8],
+  from: ColorType,
+  to: ColorType,
+  (width, height): (u32, u32),
+) -> Vec<u8> {
+  let raw16 = |data: &[u8]| -> Vec<u16> {
+    data
+      .chunks_exact(2)
+      .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+      .collect()
+  };
+  let image = match from {
+    ColorType::L8 => {
+      ImageBuffer::from_raw(width, height, data.to_vec()).map(DynamicImage::ImageLuma8)
+    }
+    ColorType::La8 => {
+      ImageBuffer::from_raw(width, height, data.to_vec()).map(DynamicImage::ImageLumaA8)
+    }
+    ColorType::Rgb8 => {
+      ImageBuffer::from_raw(width, height, data.to_vec()).map(DynamicImage::ImageRgb8)
+    }
+    ColorType::Rgba8 => {
+      ImageBuffer::from_raw(width, height, data.to_vec()).map(DynamicImage::ImageRgba8)
+    }
+    ColorType::L16 => {
+      ImageBuffer::from_raw(width, height, raw16(data)).map(DynamicImage::ImageLuma16)
+    }
+    ColorType::La16 => {
+      ImageBuffer::from_raw(width, height, raw16(data)).map(DynamicImage::ImageLumaA16)
+    }
+    ColorType::Rgb16 => {
+      ImageBuffer::from_raw(width, height, raw16(data)).map(DynamicImage::ImageRgb16)
+    }
+    ColorType::Rgba16 => {
+      ImageBuffer::from_raw(width, height, raw16(data)).map(DynamicImage::ImageRgba16)
+    }
+    other => panic!("can't convert from {:?}", other),
+  }
+  .expect("buffer size matches the dimensions");
+
+  // the channel layout at 8 bit first, then widened if asked
+  let (layout, wide) = match to {
+    ColorType::L16 => (ColorType::L8, true),
+    ColorType::La16 => (ColorType::La8, true),
+    ColorType::Rgb16 => (ColorType::Rgb8, true),
+    ColorType::Rgba16 => (ColorType::Rgba8, true),
+    other => (other, false),
+  };
+  let narrow = match layout {
+    ColorType::L8 => image.to_luma8().into_raw(),
+    ColorType::La8 => image.to_luma_alpha8().into_raw(),
+    ColorType::Rgb8 => image.to_rgb8().into_raw(),
+    ColorType::Rgba8 => image.to_rgba8().into_raw(),
+    other => panic!("can't convert to {:?}", other),
+  };
+  match wide {
+    true => narrow
+      .iter()
+      .flat_map(|&v| (v as u16 * 257).to_ne_bytes())
+      .collect(),
+    false => narrow,
+  }
+}
+
+// the color type a save ends up with: the pinned one, else rgba at the bit depth
+fn saved_color_type(opts: &SaveOptions) -> ColorType {
+  match (opts.color_type, opts.bit_depth) {
+    (Some(color_type), _) => color_type,
+    (None, 16) => ColorType::Rgba16,
+    (None, _) => ColorType::Rgba8,
   }
 }
 
+// the encoders write through these, whichever module drives them
+fn encoding_error(
+  format: ImageFormat,
+  e: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+) -> ImageError {
+  ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), e))
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>, ImageError> {
+  Ok(BufWriter::new(File::create(path)?))
+}
+
+// png outputs of `stream_pixels` or more go through the row by row encoder
+fn save_image(
+  output: &FloatingImage,
+  path: &Path,
+  format: ImageFormat,
+  color_type: ColorType,
+  quality: Option<u8>,
+  stream_pixels: u64,
+) -> Result<(), ImageError> {
+  let dimensions = (output.width, output.height);
+  if format == ImageFormat::Png && output.width as u64 * output.height as u64 >= stream_pixels {
+    return streaming::save_png(output, path, color_type);
+  }
+  // the combined buffer is always rgba8
+  let data = match color_type {
+    ColorType::Rgba8 => output.data.clone(),
+    _ => convert_color_type(&output.data, ColorType::Rgba8, color_type, dimensions),
+  };
+  // save_buffer_with_format has no way to pass the quality on
+  if let (ImageFormat::Jpeg, Some(quality)) = (format, quality) {
+    let mut file = create(path)?;
+    return JpegEncoder::new_with_quality(&mut file, quality).encode(
+      &data,
+      output.width,
+      output.height,
+      color_type,
+    );
+  }
+  image::save_buffer_with_format(path, &data, output.width, output.height, color_type, format)
+}
+
 fn find_image_from_path(path: String) -> Result<(DynamicImage, ImageFormat), ImageDataErrors> {
   match Reader::open(&path) {
     Ok(image_reader) => {
@@ -88,61 +842,550 @@ fn find_image_from_path(path: String) -> Result<(DynamicImage, ImageFormat), Ima
           Err(e) => Err(ImageDataErrors::UnableToDecodeImage(e)),
         }
       } else {
-        return Err(ImageDataErrors::UnableToFormatImage(path));
+        Err(ImageDataErrors::UnableToFormatImage(path))
       }
     }
     Err(e) => Err(ImageDataErrors::UnableToReadImageFromPath(e)),
   }
 }
 
+// header only, no pixel decode
+fn probe_dimensions(path: &str) -> Result<(u32, u32), ImageDataErrors> {
+  match Reader::open(path) {
+    Ok(image_reader) => image_reader
+      .into_dimensions()
+      .map_err(ImageDataErrors::UnableToDecodeImage),
+    Err(e) => Err(ImageDataErrors::UnableToReadImageFromPath(e)),
+  }
+}
+
+// rough peak use: both decoded inputs plus the combined output at 4 bytes per pixel.
+// resampling and --aa need more on top, so leave some headroom in the limit
+fn check_memory(args: &Args) -> Result<(), ImageDataErrors> {
+  let pixels = |(width, height): (u32, u32)| width as u64 * height as u64;
+  let pixels_1 = pixels(probe_dimensions(&args.image_1)?);
+  let pixels_2 = match args.mirror {
+    Some(_) => pixels_1,
+    None => pixels(probe_dimensions(&args.image_2)?),
+  };
+  // both inputs plus the resized copy of one of them
+  let resized = match args.size_policy {
+    SizePolicy::ShrinkToMinArea => pixels_1.min(pixels_2),
+    SizePolicy::UpscaleToMaxArea => pixels_1.max(pixels_2),
+  };
+  let estimate = 4 * (pixels_1 + pixels_2 + resized);
+  let estimate_mb = estimate.div_ceil(1024 * 1024);
+
+  if args.verbose {
+    println!("estimated memory: {} MB", estimate_mb);
+  }
+  match args.limit_memory {
+    Some(limit) if estimate_mb > limit => {
+      Err(ImageDataErrors::MemoryLimitExceeded(estimate_mb, limit))
+    }
+    _ => Ok(()),
+  }
+}
+
+// Reader::decode only ever gives the first frame, so later ones go through the gif decoder
+fn find_frame_from_path(
+  path: String,
+  frame: usize,
+) -> Result<(DynamicImage, ImageFormat), ImageDataErrors> {
+  if frame == 0 {
+    return find_image_from_path(path);
+  }
+
+  let image_format = match Reader::open(&path) {
+    Ok(image_reader) => image_reader.format(),
+    Err(e) => return Err(ImageDataErrors::UnableToReadImageFromPath(e)),
+  };
+  match image_format {
+    Some(ImageFormat::Gif) => Ok((frames::decode_gif_frame(&path, frame)?, ImageFormat::Gif)),
+    Some(_) => Err(ImageDataErrors::FrameOutOfRange(frame, 1)), // still images only have frame 0
+    None => Err(ImageDataErrors::UnableToFormatImage(path)),
+  }
+}
+
+// --frame-blend: an animated input becomes the average of its frames, stills pass through
+fn find_averaged_from_path(path: String) -> Result<(DynamicImage, ImageFormat), ImageDataErrors> {
+  let image_format = match Reader::open(&path) {
+    Ok(image_reader) => image_reader.format(),
+    Err(e) => return Err(ImageDataErrors::UnableToReadImageFromPath(e)),
+  };
+  match image_format {
+    Some(ImageFormat::Gif) => {
+      let frames = frames::decode_gif_frames(&path)?;
+      if frames.is_empty() {
+        return Err(ImageDataErrors::FrameOutOfRange(0, 0));
+      }
+      let averaged = frames::average_frames(frames);
+      Ok((DynamicImage::ImageRgba8(averaged), ImageFormat::Gif))
+    }
+    _ => find_image_from_path(path),
+  }
+}
+
+// to_rgba8 only makes sense for rgb/gray data; cmyk etc would come out with wrong colours
+fn check_color_space(path: &str) -> Result<(), ImageDataErrors> {
+  let data = match std::fs::read(path) {
+    Ok(data) => data,
+    Err(e) => return Err(ImageDataErrors::UnableToReadImageFromPath(e)),
+  };
+
+  match colorspace::detect_color_space(&data) {
+    Some(color_space) => Err(ImageDataErrors::UnsupportedColorSpace(color_space)),
+    None => Ok(()),
+  }
+}
+
+// blending raw rgb from two differently profiled images mixes their colour spaces.
+// an untagged image counts as its own (probably srgb) profile
+fn check_color_profiles(args: &Args) -> Result<(), ImageDataErrors> {
+  let read_icc = |path: &str| match std::fs::read(path) {
+    Ok(data) => Ok(colorspace::extract_icc(&data)),
+    Err(e) => Err(ImageDataErrors::UnableToReadImageFromPath(e)),
+  };
+  if read_icc(&args.image_1)? == read_icc(&args.image_2)? {
+    return Ok(());
+  }
+
+  if args.strict {
+    return Err(ImageDataErrors::ColorProfileMismatch(
+      args.image_1.clone(),
+      args.image_2.clone(),
+    ));
+  }
+  println!(
+    "warning: {} and {} have different icc profiles, their colours are blended as if they matched\n",
+    args.image_1, args.image_2
+  );
+  Ok(())
+}
+
 fn get_smallest_dimensions(dim_1: (u32, u32), dim_2: (u32, u32)) -> (u32, u32) {
   // compare number of pixels per image
   let pix_1 = dim_1.0 * dim_1.1;
   let pix_2 = dim_2.0 * dim_2.1;
 
-  return match pix_1 < pix_2 {
+  match pix_1 < pix_2 {
     true => dim_1,
     false => dim_2,
-  };
+  }
 }
 
-fn standardize_size(image_1: DynamicImage, image_2: DynamicImage) -> (DynamicImage, DynamicImage) {
-  let (width, height) = get_smallest_dimensions(image_1.dimensions(), image_2.dimensions());
-  println!("width: {}, height: {}\n", width, height);
+fn get_largest_dimensions(dim_1: (u32, u32), dim_2: (u32, u32)) -> (u32, u32) {
+  let pix_1 = dim_1.0 * dim_1.1;
+  let pix_2 = dim_2.0 * dim_2.1;
+
+  match pix_1 > pix_2 {
+    true => dim_1,
+    false => dim_2,
+  }
+}
+
+// the size both inputs are resized to; on equal areas image 2 wins either way
+fn target_dimensions(dim_1: (u32, u32), dim_2: (u32, u32), policy: SizePolicy) -> (u32, u32) {
+  match policy {
+    SizePolicy::ShrinkToMinArea => get_smallest_dimensions(dim_1, dim_2),
+    SizePolicy::UpscaleToMaxArea => get_largest_dimensions(dim_1, dim_2),
+  }
+}
+
+fn resize_filter(args: &Args) -> FilterType {
+  // supersampling looks best with lanczos3 on the way down
+  match args.filter {
+    Some(filter) => filter,
+    None if args.aa => FilterType::Lanczos3,
+    None => FilterType::Triangle,
+  }
+}
+
+fn standardize_size(
+  image_1: DynamicImage,
+  image_2: DynamicImage,
+  filter: FilterType,
+  aa: bool,
+  integer_scale: bool,
+  policy: SizePolicy,
+) -> Result<(DynamicImage, DynamicImage), ImageDataErrors> {
+  let (dim_1, dim_2) = (image_1.dimensions(), image_2.dimensions());
+  match resize_is_identity(dim_1, dim_2, filter, aa, integer_scale) {
+    true => Ok((image_1, image_2)),
+    false => standardize_size_general(image_1, image_2, filter, aa, integer_scale, policy),
+  }
+}
+
+// resample one input to the other's size, even when that is its own size
+fn standardize_size_general(
+  image_1: DynamicImage,
+  image_2: DynamicImage,
+  filter: FilterType,
+  aa: bool,
+  integer_scale: bool,
+  policy: SizePolicy,
+) -> Result<(DynamicImage, DynamicImage), ImageDataErrors> {
+  let (width, height) = target_dimensions(image_1.dimensions(), image_2.dimensions(), policy);
+
+  // pixel art: only whole-number scale factors, and nearest so edges stay crisp
+  if integer_scale {
+    let other = match image_2.dimensions() == (width, height) {
+      true => image_1.dimensions(),
+      false => image_2.dimensions(),
+    };
+    let (larger, smaller) = match policy {
+      SizePolicy::ShrinkToMinArea => (other, (width, height)),
+      SizePolicy::UpscaleToMaxArea => ((width, height), other),
+    };
+    if larger.0 % smaller.0 != 0 || larger.1 % smaller.1 != 0 {
+      return Err(ImageDataErrors::NonIntegerScale(larger, smaller));
+    }
+  }
 
-  // image 2 is smaller; resize image 1
+  let resize = |image: DynamicImage| match (integer_scale, aa) {
+    (true, _) => image.resize_exact(width, height, FilterType::Nearest),
+    (false, true) => supersample_resize(&image, width, height, filter),
+    (false, false) => image.resize_exact(width, height, filter),
+  };
+
+  // image 2 already has the target size; resize image 1
   if image_2.dimensions() == (width, height) {
-    (image_1.resize_exact(width, height, Triangle), image_2)
+    Ok((resize(image_1), image_2))
   } else {
-    (image_1, image_2.resize_exact(width, height, Triangle))
+    Ok((image_1, resize(image_2)))
+  }
+}
+
+// equal sizes need no resize at all, as long as resampling to the same size gives the
+// pixels back unchanged. gaussian and the supersampled path blur even at 1:1, so those
+// still run to keep the output the same as it always was
+fn resize_is_identity(
+  dim_1: (u32, u32),
+  dim_2: (u32, u32),
+  filter: FilterType,
+  aa: bool,
+  integer_scale: bool, // always nearest
+) -> bool {
+  dim_1 == dim_2 && (integer_scale || (!aa && filter != FilterType::Gaussian))
+}
+
+// cheap supersample AA: blow the source up 2x with nearest, then filter down to the target.
+// the 2x copy holds 4x the pixels, so this costs noticeably more memory and time
+fn supersample_resize(
+  image: &DynamicImage,
+  width: u32,
+  height: u32,
+  filter: FilterType,
+) -> DynamicImage {
+  let (src_width, src_height) = image.dimensions();
+  image
+    .resize_exact(src_width * 2, src_height * 2, FilterType::Nearest)
+    .resize_exact(width, height, filter)
+}
+
+#[cfg(feature = "auto-align")]
+fn align_images(image_1: &DynamicImage, image_2: DynamicImage) -> DynamicImage {
+  let (width, height) = image_1.dimensions();
+  let (dx, dy) = align::estimate_shift(
+    image_1.to_rgba8().as_raw(),
+    image_2.to_rgba8().as_raw(),
+    width,
+    height,
+  );
+  println!("auto-align shift: ({}, {})\n", dx, dy);
+  align::shift_image(&image_2, dx, dy)
+}
+
+fn compare_sheet(
+  image_1: &DynamicImage,
+  image_2: &DynamicImage,
+  args: &Args,
+) -> Result<FloatingImage, ImageDataErrors> {
+  let sheet = compare::compare_images(
+    image_1,
+    image_2,
+    args.divider_width,
+    args.divider_color,
+    (args.label_1.as_deref(), args.label_2.as_deref()),
+    args.label_color,
+  );
+
+  let mut output = FloatingImage::new(sheet.width(), sheet.height(), args.output.clone());
+  output.set_data(sheet.into_raw())?;
+  Ok(output)
+}
+
+fn combine_images(
+  image_1: &DynamicImage,
+  image_2: &DynamicImage,
+  blender: Option<&dyn Blender>, // None -> alternate pixels
+  premultiply: bool,
+  stride: u32, // > 1 -> low-res preview, every nth pixel in both directions
+  jitter: Jitter,
+) -> Result<Vec<u8>, ImageDataErrors> {
+  // the common case of two rgba8 inputs at full resolution doesn't need its own copies
+  let premultiplied = premultiply && blender.is_some();
+  if let (Some(rgba_1), Some(rgba_2), 1, false) = (
+    image_1.as_rgba8(),
+    image_2.as_rgba8(),
+    stride,
+    premultiplied,
+  ) {
+    if rgba_1.dimensions() == rgba_2.dimensions() {
+      let (width, height) = rgba_1.dimensions();
+      let pattern = alternate_pattern(width, height, jitter);
+      return combine_rgba_direct(
+        rgba_1.as_raw(),
+        rgba_2.as_raw(),
+        (width, height),
+        blender,
+        pattern,
+      );
+    }
   }
+  combine_images_general(image_1, image_2, blender, premultiply, stride, jitter)
 }
 
-fn combine_images(image_1: DynamicImage, image_2: DynamicImage) -> Vec<u8> {
-  let vec_1: Vec<u8> = image_1.to_rgba8().into_vec();
-  let vec_2: Vec<u8> = image_2.to_rgba8().into_vec();
+// fast path: blend straight from the decoded buffers into one preallocated output
+fn combine_rgba_direct(
+  vec_1: &[u8],
+  vec_2: &[u8],
+  dimensions: (u32, u32),
+  blender: Option<&dyn Blender>,
+  takes_image_1: impl Fn(usize) -> bool,
+) -> Result<Vec<u8>, ImageDataErrors> {
+  check_pixel_buffers(vec_1, vec_2, dimensions)?;
+  if let Some(blender) = blender {
+    return Ok(blend::blend_pixels(vec_1, vec_2, blender));
+  }
+  let mut combined_data = Vec::with_capacity(vec_1.len());
+  for (pixel, (a, b)) in vec_1.chunks_exact(4).zip(vec_2.chunks_exact(4)).enumerate() {
+    combined_data.extend_from_slice(match takes_image_1(pixel) {
+      true => a,
+      false => b,
+    });
+  }
+  Ok(combined_data)
+}
 
-  alternate_pixels(vec_1, vec_2)
+// any color type, any stride; the inputs are converted to rgba8 copies first
+fn combine_images_general(
+  image_1: &DynamicImage,
+  image_2: &DynamicImage,
+  blender: Option<&dyn Blender>,
+  premultiply: bool,
+  stride: u32,
+  jitter: Jitter,
+) -> Result<Vec<u8>, ImageDataErrors> {
+  let (width, height) = image_1.dimensions();
+  let mut vec_1: Vec<u8> = image_1.to_rgba8().into_vec();
+  let mut vec_2: Vec<u8> = image_2.to_rgba8().into_vec();
+
+  // alternating only picks pixels, no blend math, so the alpha space doesn't matter
+  let blender = match blender {
+    Some(blender) => blender,
+    None => return alternate_pixels(vec_1, vec_2, (width, height), stride, jitter),
+  };
+
+  if stride > 1 {
+    vec_1 = sample_pixels(&vec_1, width, height, stride);
+    vec_2 = sample_pixels(&vec_2, width, height, stride);
+  }
+
+  // blend in premultiplied space so transparent pixels don't drag their colour in
+  if premultiply {
+    blend::premultiply(&mut vec_1);
+    blend::premultiply(&mut vec_2);
+  }
+
+  let mut combined_data = blend::blend_pixels(&vec_1, &vec_2, blender);
+
+  if premultiply {
+    blend::unpremultiply(&mut combined_data);
+  }
+  Ok(combined_data)
 }
 
-fn alternate_pixels(vec_1: Vec<u8>, vec_2: Vec<u8>) -> Vec<u8> {
-  // if vec1.len == n, -> [00, 01, 02... 0n]
-  let mut combined_data = vec![0u8; vec_1.len()];
+// like combine_images, but mixed per pixel by a full resolution mask
+fn combine_masked(
+  image_1: &DynamicImage,
+  image_2: &DynamicImage,
+  mask: &[u8],
+  args: &Args,
+) -> Vec<u8> {
+  let (width, height) = image_1.dimensions();
+  let stride = args.preview_scale;
+  let mut vec_1: Vec<u8> = image_1.to_rgba8().into_vec();
+  let mut vec_2: Vec<u8> = image_2.to_rgba8().into_vec();
+  let mut mask = mask.to_vec();
 
-  let mut i = 0;
-  while i < vec_1.len() {
-    if i % 8 == 0 {
-      combined_data.splice(i..=i + 3, set_rgba(&vec_1, i, i + 3));
+  if stride > 1 {
+    vec_1 = sample_pixels(&vec_1, width, height, stride);
+    vec_2 = sample_pixels(&vec_2, width, height, stride);
+    mask = sampled_offsets(width, height, stride)
+      .map(|offset| mask[offset / 4])
+      .collect();
+  }
+
+  if args.premultiply {
+    blend::premultiply(&mut vec_1);
+    blend::premultiply(&mut vec_2);
+  }
+  let mut combined_data = blend::blend_masked(&vec_1, &vec_2, &mask, args.rounding);
+  if args.premultiply {
+    blend::unpremultiply(&mut combined_data);
+  }
+  combined_data
+}
+
+// like combine_images, but the share of image 2 follows the pixel position
+fn combine_gradient(
+  image_1: &DynamicImage,
+  image_2: &DynamicImage,
+  direction: blend::Direction,
+  premultiply: bool,
+  rounding: blend::Rounding,
+  stride: u32,
+) -> Vec<u8> {
+  let (width, height) = image_1.dimensions();
+  let mut vec_1: Vec<u8> = image_1.to_rgba8().into_vec();
+  let mut vec_2: Vec<u8> = image_2.to_rgba8().into_vec();
+
+  // the gradient spans the preview canvas, so it still runs edge to edge
+  if stride > 1 {
+    vec_1 = sample_pixels(&vec_1, width, height, stride);
+    vec_2 = sample_pixels(&vec_2, width, height, stride);
+  }
+  let (width, height) = preview_dimensions(width, height, stride);
+
+  if premultiply {
+    blend::premultiply(&mut vec_1);
+    blend::premultiply(&mut vec_2);
+  }
+  let mut combined_data = blend::blend_gradient(&vec_1, &vec_2, width, height, direction, rounding);
+  if premultiply {
+    blend::unpremultiply(&mut combined_data);
+  }
+  combined_data
+}
+
+// size of the combined image when only every nth pixel is used
+fn preview_dimensions(width: u32, height: u32, stride: u32) -> (u32, u32) {
+  ((width / stride).max(1), (height / stride).max(1))
+}
+
+// byte offset of every pixel a preview with this stride looks at, row by row
+fn sampled_offsets(width: u32, height: u32, stride: u32) -> impl Iterator<Item = usize> {
+  let (preview_width, preview_height) = preview_dimensions(width, height, stride);
+  (0..preview_height).flat_map(move |y| {
+    (0..preview_width).map(move |x| ((y * stride * width + x * stride) * 4) as usize)
+  })
+}
+
+fn sample_pixels(vec: &[u8], width: u32, height: u32, stride: u32) -> Vec<u8> {
+  let mut sampled = Vec::new();
+  for offset in sampled_offsets(width, height, stride) {
+    sampled.extend_from_slice(&vec[offset..offset + 4]);
+  }
+  sampled
+}
+
+// the alternate pattern: even pixels of the output come from image 1, odd ones from image 2.
+// with --jitter every row is shifted by its own 0..=N pixels; the pattern repeats every
+// 2 pixels, so an odd shift makes image 2 lead that row. true -> image 1 at that pixel
+fn alternate_pattern(width: u32, height: u32, jitter: Jitter) -> impl Fn(usize) -> bool {
+  let shifts: Vec<usize> = (0..height).map(|row| jitter.row_shift(row)).collect();
+  move |pixel| (pixel + shifts[pixel / width as usize]).is_multiple_of(2)
+}
+
+// an alternate output pulled apart by source: each buffer keeps only the pixels that
+// came from its image, everything else is transparent
+fn split_alternate(data: &[u8], width: u32, jitter: Jitter) -> (Vec<u8>, Vec<u8>) {
+  let mut from_1 = vec![0u8; data.len()];
+  let mut from_2 = vec![0u8; data.len()];
+  let takes_image_1 = alternate_pattern(width, (data.len() / 4) as u32 / width, jitter);
+  for (pixel, rgba) in data.chunks_exact(4).enumerate() {
+    let target = match takes_image_1(pixel) {
+      true => &mut from_1,
+      false => &mut from_2,
+    };
+    target[pixel * 4..pixel * 4 + 4].copy_from_slice(rgba);
+  }
+  (from_1, from_2)
+}
+
+// --split-output: <prefix>_1.png and <prefix>_2.png next to the normal output.
+// always png, the transparent gaps are the point
+fn save_split(output: &FloatingImage, prefix: &str, args: &Args) -> Result<(), ImageDataErrors> {
+  let opts = SaveOptions {
+    format: ImageFormat::Png,
+    bit_depth: 8,
+    exif_source: None,
+    sources: Vec::new(),
+    quantize: None,
+    quality: None,
+    verify: args.verify,
+    color_type: None,
+  };
+  let (from_1, from_2) = split_alternate(&output.data, output.width, args.jitter);
+  for (n, data) in [(1, from_1), (2, from_2)] {
+    let mut part = FloatingImage::new(output.width, output.height, format!("{}_{}.png", prefix, n));
+    part.set_data(data)?;
+    write_output(&part, &part.name, ImageFormat::Png, &opts)?;
+  }
+  Ok(())
+}
+
+fn alternate_pixels(
+  vec_1: Vec<u8>,
+  vec_2: Vec<u8>,
+  dimensions: (u32, u32),
+  stride: u32,
+  jitter: Jitter,
+) -> Result<Vec<u8>, ImageDataErrors> {
+  // the loop below copies 4 bytes at a time, a partial pixel at the end would run off it
+  check_pixel_buffers(&vec_1, &vec_2, dimensions)?;
+  let (width, height) = dimensions;
+  let (preview_width, preview_height) = preview_dimensions(width, height, stride);
+  let mut combined_data = vec![0u8; (preview_width * preview_height * 4) as usize];
+  let takes_image_1 = alternate_pattern(preview_width, preview_height, jitter);
+
+  // i walks the output, offset walks the (possibly strided) source
+  for (i, offset) in (0..combined_data.len())
+    .step_by(4) // we use rgba
+    .zip(sampled_offsets(width, height, stride))
+  {
+    if takes_image_1(i / 4) {
+      combined_data.splice(i..=i + 3, set_rgba(&vec_1, offset, offset + 3));
     } else {
-      combined_data.splice(i..=i + 3, set_rgba(&vec_2, i, i + 3));
+      combined_data.splice(i..=i + 3, set_rgba(&vec_2, offset, offset + 3));
     }
-    i += 4; // we use rgba
   }
 
-  return combined_data;
+  Ok(combined_data)
 }
 
-fn set_rgba(vec: &Vec<u8>, start: usize, end: usize) -> Vec<u8> {
+// both inputs have to be exactly width x height rgba pixels before any of them is copied,
+// and an image with no columns has no rows to walk either
+fn check_pixel_buffers(
+  vec_1: &[u8],
+  vec_2: &[u8],
+  (width, height): (u32, u32),
+) -> Result<(), ImageDataErrors> {
+  let expected = (width as usize)
+    .checked_mul(height as usize)
+    .and_then(|pixels| pixels.checked_mul(4))
+    .filter(|_| width > 0);
+  match [vec_1.len(), vec_2.len()]
+    .into_iter()
+    .find(|&length| Some(length) != expected)
+  {
+    Some(length) => Err(ImageDataErrors::MalformedBuffer(length, (width, height))),
+    None => Ok(()),
+  }
+}
+
+fn set_rgba(vec: &[u8], start: usize, end: usize) -> Vec<u8> {
   let mut rgba: Vec<u8> = Vec::new();
   for i in start..=end {
     let val: u8 = match vec.get(i) {
@@ -151,5 +1394,173 @@ fn set_rgba(vec: &Vec<u8>, start: usize, end: usize) -> Vec<u8> {
     };
     rgba.push(val);
   }
-  return rgba;
+  rgba
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use combiner::blend::{BlendMode, Rounding};
+  use image::RgbaImage;
+  use rng::XorShift;
+
+  fn random_rgba(rng: &mut XorShift, width: u32, height: u32) -> DynamicImage {
+    let mut data = vec![0u8; (width * height * 4) as usize];
+    rng.fill_bytes(&mut data);
+    DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, data).unwrap())
+  }
+
+  #[test]
+  fn equal_size_fast_path_matches_general() {
+    let mut rng = XorShift::new(7);
+    let (image_1, image_2) = (random_rgba(&mut rng, 13, 7), random_rgba(&mut rng, 13, 7));
+    let modes = [
+      BlendMode::Alternate,
+      BlendMode::Average,
+      BlendMode::Over {
+        image_2_on_top: true,
+      },
+      BlendMode::Weighted(0.3),
+      BlendMode::Hue,
+    ];
+    let jitters = [
+      Jitter::default(),
+      Jitter {
+        amount: 3,
+        seed: 11,
+      },
+    ];
+    for mode in modes {
+      for jitter in jitters {
+        let blender = mode.blender(false, Rounding::Nearest);
+        let fast = combine_images(&image_1, &image_2, blender.as_deref(), false, 1, jitter);
+        let general =
+          combine_images_general(&image_1, &image_2, blender.as_deref(), false, 1, jitter);
+        assert_eq!(fast.unwrap(), general.unwrap(), "{:?} {:?}", mode, jitter);
+      }
+    }
+  }
+
+  #[test]
+  fn equal_size_skips_resize_without_changing_pixels() {
+    let mut rng = XorShift::new(3);
+    let (image_1, image_2) = (random_rgba(&mut rng, 9, 5), random_rgba(&mut rng, 9, 5));
+    for filter in [
+      FilterType::Nearest,
+      FilterType::Triangle,
+      FilterType::CatmullRom,
+      FilterType::Lanczos3,
+    ] {
+      assert!(resize_is_identity((9, 5), (9, 5), filter, false, false));
+      let (fast_1, fast_2) = standardize_size(
+        image_1.clone(),
+        image_2.clone(),
+        filter,
+        false,
+        false,
+        SizePolicy::ShrinkToMinArea,
+      )
+      .unwrap();
+      let (general_1, general_2) = standardize_size_general(
+        image_1.clone(),
+        image_2.clone(),
+        filter,
+        false,
+        false,
+        SizePolicy::ShrinkToMinArea,
+      )
+      .unwrap();
+      assert_eq!(fast_1.to_rgba8(), general_1.to_rgba8(), "{:?}", filter);
+      assert_eq!(fast_2.to_rgba8(), general_2.to_rgba8(), "{:?}", filter);
+    }
+    // gaussian blurs even at 1:1, so it keeps the resample
+    assert!(!resize_is_identity(
+      (9, 5),
+      (9, 5),
+      FilterType::Gaussian,
+      false,
+      false
+    ));
+  }
+
+  #[test]
+  fn jitter_0_is_plain_alternate_and_seeds_repeat() {
+    let mut rng = XorShift::new(5);
+    let (image_1, image_2) = (random_rgba(&mut rng, 11, 6), random_rgba(&mut rng, 11, 6));
+    let (raw_1, raw_2) = (image_1.to_rgba8().into_raw(), image_2.to_rgba8().into_raw());
+    let combine = |jitter| combine_images(&image_1, &image_2, None, false, 1, jitter).unwrap();
+
+    // even pixels from image 1, odd ones from image 2, whatever the seed
+    let plain: Vec<u8> = (0..11 * 6)
+      .flat_map(|pixel| match pixel % 2 {
+        0 => raw_1[pixel * 4..pixel * 4 + 4].to_vec(),
+        _ => raw_2[pixel * 4..pixel * 4 + 4].to_vec(),
+      })
+      .collect();
+    assert_eq!(combine(Jitter::default()), plain);
+    assert_eq!(
+      combine(Jitter {
+        amount: 0,
+        seed: 42
+      }),
+      plain
+    );
+
+    let jittered = Jitter {
+      amount: 5,
+      seed: 42,
+    };
+    assert_eq!(combine(jittered), combine(jittered));
+    assert_ne!(combine(jittered), plain);
+    // every row is still a strict alternation, led by one image or the other
+    let output = combine(jittered);
+    for (pixel, rgba) in output.chunks_exact(4).enumerate() {
+      let leads_with_1 = jittered.row_shift((pixel / 11) as u32).is_multiple_of(2);
+      let from_1 = (pixel % 2 == 0) == leads_with_1;
+      let source = if from_1 { &raw_1 } else { &raw_2 };
+      assert_eq!(rgba, &source[pixel * 4..pixel * 4 + 4], "pixel {}", pixel);
+    }
+  }
+
+  #[test]
+  fn alternate_pixels_rejects_buffers_that_dont_match_the_size() {
+    let jitter = Jitter::default();
+    let malformed = |vec_1: Vec<u8>, vec_2: Vec<u8>, dimensions| {
+      matches!(
+        alternate_pixels(vec_1, vec_2, dimensions, 1, jitter),
+        Err(ImageDataErrors::MalformedBuffer(..))
+      )
+    };
+    // a stray byte, a missing byte, lengths that disagree and an image with no columns
+    assert!(malformed(vec![0; 17], vec![0; 17], (2, 2)));
+    assert!(malformed(vec![0; 15], vec![0; 15], (2, 2)));
+    assert!(malformed(vec![0; 16], vec![0; 32], (2, 2)));
+    assert!(malformed(vec![0; 16], vec![0; 16], (4, 2)));
+    assert!(malformed(vec![0; 16], vec![0; 16], (0, 2)));
+  }
+
+  #[test]
+  fn alternate_pixels_alternates_a_whole_buffer() {
+    let combined =
+      alternate_pixels(vec![1; 16], vec![2; 16], (2, 2), 1, Jitter::default()).unwrap();
+    assert_eq!(combined.len(), 16);
+    assert_eq!(&combined[..8], &[1, 1, 1, 1, 2, 2, 2, 2]);
+  }
+
+  #[test]
+  fn fast_path_rejects_partial_pixels() {
+    let average = blend::Average {
+      rounding: Rounding::Nearest,
+    };
+    for blender in [None, Some(&average as &dyn Blender)] {
+      for length in [17, 15] {
+        let buffer = vec![0u8; length];
+        assert!(matches!(
+          combine_rgba_direct(&buffer, &buffer, (2, 2), blender, |_| true),
+          Err(ImageDataErrors::MalformedBuffer(l, (2, 2))) if l == length
+        ));
+      }
+      assert!(combine_rgba_direct(&[0; 16], &[0; 16], (2, 2), blender, |_| true).is_ok());
+    }
+  }
 }
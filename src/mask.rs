@@ -0,0 +1,47 @@
+// single channel blend masks: 0 keeps image 1, 255 takes image 2, in between mixes
+use crate::ImageDataErrors;
+use crate::{filters, find_image_from_path};
+use image::imageops::FilterType;
+
+// a grayscale mask file, luma stretched to the given size
+pub fn load_mask(
+  path: &str,
+  width: u32,
+  height: u32,
+  filter: FilterType,
+) -> Result<Vec<u8>, ImageDataErrors> {
+  let (mask, _) = find_image_from_path(path.to_string())?;
+  let resized = mask.resize_exact(width, height, filter);
+  Ok(resized.to_luma8().into_raw())
+}
+
+// largest rgb difference per pixel of two equally sized rgba buffers, so pixels where
+// the images disagree lean towards image 2. with a threshold the mask is hard:
+// 255 above it, 0 at or below
+pub fn mask_from_diff(a: &[u8], b: &[u8], threshold: Option<u8>) -> Vec<u8> {
+  a.chunks_exact(4)
+    .zip(b.chunks_exact(4))
+    .map(|(a, b)| {
+      let diff = (0..3).map(|i| a[i].abs_diff(b[i])).max().unwrap_or(0);
+      match threshold {
+        Some(threshold) if diff > threshold => 255,
+        Some(_) => 0,
+        None => diff,
+      }
+    })
+    .collect()
+}
+
+// --alpha-from: the mask replaces the alpha of an rgba buffer of the same size
+pub fn apply_alpha_mask(data: &mut [u8], mask: &[u8]) {
+  for (pixel, &alpha) in data.chunks_exact_mut(4).zip(mask) {
+    pixel[3] = alpha;
+  }
+}
+
+// soften mask edges; a radius of 0 leaves the mask alone
+pub fn feather(mask: &mut [u8], width: u32, height: u32, radius: u32) {
+  if radius > 0 {
+    filters::box_blur(mask, width, height, radius);
+  }
+}
@@ -0,0 +1,376 @@
+// the two inputs and their formats, carried through open -> standardize -> combine -> save
+use crate::args::Args;
+use crate::rng::XorShift;
+use crate::{
+  check_color_profiles, check_color_space, check_memory, combine_gradient, combine_images,
+  combine_masked, compare_sheet, find_averaged_from_path, find_frame_from_path, input_bit_depth,
+  preview_dimensions, resize_filter, sample_pixels, standardize_size, supports_16_bit,
+  target_dimensions, write_output, FloatingImage, ImageDataErrors,
+};
+use crate::{filters, mask, stats, tile};
+use combiner::blend::{self, BlendMode, Over};
+use image::imageops::FilterType;
+use image::{ColorType, DynamicImage, GenericImageView, GrayImage, ImageFormat, RgbaImage};
+
+// which way image 1 is flipped to stand in for image 2
+#[derive(Debug, Clone, Copy)]
+pub enum Mirror {
+  Horizontal,
+  Vertical,
+}
+
+// which size both inputs end up at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizePolicy {
+  #[default]
+  ShrinkToMinArea, // the input with fewer pixels, nothing is upscaled
+  UpscaleToMaxArea, // the input with more pixels, nothing is thrown away
+}
+
+// --jitter/--seed: each row of the alternate pattern starts up to `amount` pixels late
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Jitter {
+  pub amount: u32,
+  pub seed: u64,
+}
+
+impl Jitter {
+  // 0..=amount, the same for a given seed and row on every run
+  pub fn row_shift(self, row: u32) -> usize {
+    match self.amount {
+      0 => 0,
+      amount => (XorShift::keyed(self.seed, row as u64).next_u64() % (amount as u64 + 1)) as usize,
+    }
+  }
+}
+
+pub struct ImagePair {
+  pub img1: DynamicImage,
+  pub img2: DynamicImage,
+  pub format1: ImageFormat,
+  pub format2: ImageFormat,
+}
+
+// what to do with the combined buffer besides encoding it
+pub struct SaveOptions<'a> {
+  pub format: ImageFormat,
+  pub bit_depth: u8,
+  pub exif_source: Option<&'a str>, // copy exif from this file into the output
+  pub sources: Vec<&'a str>,        // input paths for png text chunks, empty -> none
+  pub quantize: Option<u16>,        // palette size; bit_depth is ignored when set
+  pub quality: Option<u8>,          // jpeg quality, None -> the encoder's default
+  pub verify: bool,                 // decode the file again and compare
+  pub color_type: Option<ColorType>, // converted to on save; bit_depth is ignored when set
+}
+
+impl ImagePair {
+  // read the requested frame of both inputs; they have to share a format.
+  // with --mirror there is only one input and image 2 is its flipped copy
+  pub fn open(args: &Args) -> Result<Self, ImageDataErrors> {
+    if !args.assume_srgb {
+      check_color_space(&args.image_1)?;
+      if args.mirror.is_none() {
+        check_color_space(&args.image_2)?;
+        check_color_profiles(args)?;
+      }
+    }
+    if args.limit_memory.is_some() || args.verbose {
+      check_memory(args)?;
+    }
+
+    let read = |path: &str, frame: usize| match args.frame_blend {
+      true => find_averaged_from_path(path.to_string()),
+      false => find_frame_from_path(path.to_string(), frame),
+    };
+    let (img1, format1) = read(&args.image_1, args.frame_1)?;
+    let (img2, format2) = match args.mirror {
+      Some(Mirror::Horizontal) => (img1.fliph(), format1),
+      Some(Mirror::Vertical) => (img1.flipv(), format1),
+      None => read(&args.image_2, args.frame_2)?,
+    };
+    let pair = ImagePair {
+      img1,
+      img2,
+      format1,
+      format2,
+    };
+
+    if pair.format1 != pair.format2 {
+      return Err(ImageDataErrors::DifferentImageFormats);
+    }
+    Ok(pair)
+  }
+
+  pub fn dimensions(&self) -> (u32, u32) {
+    self.img1.dimensions()
+  }
+
+  // None -> follow the inputs, as far as the output format allows.
+  // checked before any work so a bad request fails fast
+  pub fn output_bit_depth(
+    &self,
+    requested: Option<u8>,
+    format: ImageFormat,
+  ) -> Result<u8, ImageDataErrors> {
+    match requested {
+      Some(16) if !supports_16_bit(format) => Err(ImageDataErrors::UnsupportedBitDepth(16, format)),
+      Some(depth) => Ok(depth),
+      None if !supports_16_bit(format) => Ok(8),
+      None => Ok(input_bit_depth(&self.img1).max(input_bit_depth(&self.img2))),
+    }
+  }
+
+  // --auto-contrast: stretch each input to the full range on its own, before resizing
+  pub fn auto_contrast(self, clip: f32) -> Self {
+    ImagePair {
+      img1: filters::auto_contrast(self.img1, clip),
+      img2: filters::auto_contrast(self.img2, clip),
+      ..self
+    }
+  }
+
+  // --edge-1/--edge-2: swap an input for its sobel edge map, before resizing
+  pub fn edge_detect(self, edge_1: bool, edge_2: bool) -> Self {
+    let detect = |image: DynamicImage, enabled: bool| match enabled {
+      true => filters::sobel(image),
+      false => image,
+    };
+    ImagePair {
+      img1: detect(self.img1, edge_1),
+      img2: detect(self.img2, edge_2),
+      ..self
+    }
+  }
+
+  // resize one image to the other's size, which one depends on --normalize-sizes
+  pub fn standardize(self, args: &Args) -> Result<Self, ImageDataErrors> {
+    if args.verbose {
+      let (dim_1, dim_2) = (self.img1.dimensions(), self.img2.dimensions());
+      let target = target_dimensions(dim_1, dim_2, args.size_policy);
+      let reason = match args.size_policy {
+        SizePolicy::ShrinkToMinArea => "smaller area, shrink-to-min-area",
+        SizePolicy::UpscaleToMaxArea => "larger area, upscale-to-max-area",
+      };
+      println!(
+        "sizes: image 1 {}x{}, image 2 {}x{} -> image {}'s {}x{} ({})",
+        dim_1.0,
+        dim_1.1,
+        dim_2.0,
+        dim_2.1,
+        if target == dim_2 { 2 } else { 1 },
+        target.0,
+        target.1,
+        reason
+      );
+    }
+    let (img1, img2) = standardize_size(
+      self.img1,
+      self.img2,
+      resize_filter(args),
+      args.aa,
+      args.integer_scale,
+      args.size_policy,
+    )?;
+    Ok(ImagePair { img1, img2, ..self })
+  }
+
+  // --background-tile instead of standardize: both become canvas sized layers, image 1
+  // at its own size on a transparent canvas and image 2 repeated under it
+  pub fn tile_background(self, args: &Args) -> Self {
+    let canvas = args.canvas.unwrap_or((
+      self.img1.width().max(self.img2.width()),
+      self.img1.height().max(self.img2.height()),
+    ));
+    let (img1, img2) = tile::layers(&self.img1, &self.img2, canvas, args.anchor);
+    ImagePair { img1, img2, ..self }
+  }
+
+  // --max-dimension: shrink both, now the same size, until the longer side fits. before
+  // the combine, so alternating still picks whole output pixels instead of getting blurred
+  pub fn fit_within(self, max_dimension: u32, filter: FilterType) -> Self {
+    let (width, height) = self.dimensions();
+    if width.max(height) <= max_dimension {
+      return self;
+    }
+    ImagePair {
+      img1: self.img1.resize(max_dimension, max_dimension, filter),
+      img2: self.img2.resize(max_dimension, max_dimension, filter),
+      ..self
+    }
+  }
+
+  // alternate starts with image 1, so put whichever input has more detail there
+  pub fn lead_with_sharper(self, verbose: bool) -> Self {
+    let (width, height) = self.dimensions();
+    let sharpness_1 = stats::sharpness(self.img1.to_rgba8().as_raw(), width, height);
+    let sharpness_2 = stats::sharpness(self.img2.to_rgba8().as_raw(), width, height);
+    let swap = sharpness_2 > sharpness_1;
+    if verbose {
+      println!(
+        "sharpness: image 1 {:.2}, image 2 {:.2} -> image {} leads",
+        sharpness_1,
+        sharpness_2,
+        if swap { 2 } else { 1 }
+      );
+    }
+
+    match swap {
+      true => ImagePair {
+        img1: self.img2,
+        img2: self.img1,
+        format1: self.format2,
+        format2: self.format1,
+      },
+      false => self,
+    }
+  }
+
+  #[cfg(feature = "auto-align")]
+  pub fn align(self) -> Self {
+    let img2 = crate::align_images(&self.img1, self.img2);
+    ImagePair { img2, ..self }
+  }
+
+  // --mask or --mask-from-diff at the standardized size, feathered
+  pub fn blend_mask(&self, args: &Args) -> Result<Option<Vec<u8>>, ImageDataErrors> {
+    let (width, height) = self.dimensions();
+    let mut blend_mask = match (&args.mask, args.mask_from_diff) {
+      (Some(path), _) => mask::load_mask(path, width, height, resize_filter(args))?,
+      (None, true) => mask::mask_from_diff(
+        self.img1.to_rgba8().as_raw(),
+        self.img2.to_rgba8().as_raw(),
+        args.mask_threshold,
+      ),
+      (None, false) => return Ok(None),
+    };
+    mask::feather(&mut blend_mask, width, height, args.mask_feather);
+    Ok(Some(blend_mask))
+  }
+
+  // warn about a fully transparent input; with --skip-empty the other image is
+  // returned so it can be saved as is instead of being blended with nothing
+  fn empty_input(&self, args: &Args) -> Option<&DynamicImage> {
+    // images without an alpha channel can't be transparent, no need to convert them
+    let is_empty = |image: &DynamicImage| {
+      image.color().has_alpha() && stats::is_fully_transparent(image.to_rgba8().as_raw())
+    };
+    let (empty, other, kept) = match (is_empty(&self.img1), is_empty(&self.img2)) {
+      (true, false) => (1, 2, &self.img2),
+      (false, true) => (2, 1, &self.img1),
+      (true, true) => {
+        println!("warning: both images are fully transparent, so is the output\n");
+        return None;
+      }
+      (false, false) => return None,
+    };
+
+    match args.skip_empty {
+      true => {
+        println!(
+          "image {} is fully transparent, --skip-empty keeps image {} as is\n",
+          empty, other
+        );
+        Some(kept)
+      }
+      false => {
+        println!(
+          "warning: image {} is fully transparent, the blend may just fade or hide image {} (--skip-empty keeps image {} as is)\n",
+          empty, other, other
+        );
+        None
+      }
+    }
+  }
+
+  // --drop-shadow: the top image's shadow drawn onto the bottom image, so the plain
+  // over blend then puts the top image on both
+  fn shadowed_bottom(&self, image_2_on_top: bool, args: &Args) -> DynamicImage {
+    let (top, bottom) = match image_2_on_top {
+      true => (&self.img2, &self.img1),
+      false => (&self.img1, &self.img2),
+    };
+    let shadow = args.drop_shadow.unwrap();
+    let alpha = top.to_rgba8().pixels().map(|pixel| pixel[3]).collect();
+    let top_alpha = GrayImage::from_raw(top.width(), top.height(), alpha).unwrap();
+    let shadow_image = filters::drop_shadow(
+      &top_alpha,
+      shadow.offset,
+      shadow.blur,
+      shadow.opacity,
+      shadow.color,
+    );
+    let over = Over {
+      premultiplied: false,
+      rounding: args.rounding,
+      image_2_on_top: false,
+    };
+    let shadowed = blend::blend_pixels(shadow_image.as_raw(), bottom.to_rgba8().as_raw(), &over);
+    DynamicImage::ImageRgba8(RgbaImage::from_raw(top.width(), top.height(), shadowed).unwrap())
+  }
+
+  // a compare sheet when asked for, otherwise the blend of both images.
+  // a mask replaces the blend mode
+  pub fn combine(&self, mode: BlendMode, args: &Args) -> Result<FloatingImage, ImageDataErrors> {
+    if args.compare {
+      return compare_sheet(&self.img1, &self.img2, args);
+    }
+
+    let (width, height) = self.dimensions();
+    let (width, height) = preview_dimensions(width, height, args.preview_scale);
+    let mut output = FloatingImage::new(width, height, args.output.clone());
+    if let Some(kept) = self.empty_input(args) {
+      let data = kept.to_rgba8().into_raw();
+      output.set_data(match args.preview_scale {
+        1 => data,
+        stride => sample_pixels(&data, kept.width(), kept.height(), stride),
+      })?;
+      return Ok(output);
+    }
+    let combined_data = match (self.blend_mask(args)?, mode) {
+      (Some(blend_mask), _) => combine_masked(&self.img1, &self.img2, &blend_mask, args),
+      (None, BlendMode::Over { image_2_on_top }) if args.drop_shadow.is_some() => {
+        let bottom = self.shadowed_bottom(image_2_on_top, args);
+        let (img1, img2) = match image_2_on_top {
+          true => (&bottom, &self.img2),
+          false => (&self.img1, &bottom),
+        };
+        combine_images(
+          img1,
+          img2,
+          mode.blender(args.premultiply, args.rounding).as_deref(),
+          args.premultiply,
+          args.preview_scale,
+          args.jitter,
+        )?
+      }
+      (None, BlendMode::Gradient(direction)) => combine_gradient(
+        &self.img1,
+        &self.img2,
+        direction,
+        args.premultiply,
+        args.rounding,
+        args.preview_scale,
+      ),
+      (None, _) => combine_images(
+        &self.img1,
+        &self.img2,
+        mode.blender(args.premultiply, args.rounding).as_deref(),
+        args.premultiply,
+        args.preview_scale,
+        args.jitter,
+      )?,
+    };
+    output.set_data(combined_data)?;
+    Ok(output)
+  }
+
+  // the last step: write what combine made to `path`
+  pub fn save(
+    &self,
+    combined: &FloatingImage,
+    path: &str,
+    opts: &SaveOptions,
+  ) -> Result<(), ImageDataErrors> {
+    write_output(combined, path, opts.format, opts)
+  }
+}
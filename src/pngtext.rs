@@ -0,0 +1,45 @@
+// --embed-sources: text chunks with the input paths, inserted into an encoded png
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+fn chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+  let mut crc = crc32fast::Hasher::new();
+  crc.update(chunk_type);
+  crc.update(data);
+
+  let mut chunk = Vec::with_capacity(data.len() + 12);
+  chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  chunk.extend_from_slice(chunk_type);
+  chunk.extend_from_slice(data);
+  chunk.extend_from_slice(&crc.finalize().to_be_bytes());
+  chunk
+}
+
+// tEXt is latin-1 only; anything else goes in an uncompressed iTXt, which is utf-8
+fn text_chunk(keyword: &str, text: &str) -> Vec<u8> {
+  let latin_1: Option<Vec<u8>> = text.chars().map(|c| u8::try_from(c as u32).ok()).collect();
+  match latin_1 {
+    Some(text) => chunk(b"tEXt", &[keyword.as_bytes(), &[0], &text].concat()),
+    // keyword, compression flag and method, then empty language tag and translated keyword
+    None => chunk(
+      b"iTXt",
+      &[keyword.as_bytes(), &[0, 0, 0, 0, 0], text.as_bytes()].concat(),
+    ),
+  }
+}
+
+// the chunks go right after IHDR, which is always first and 13 bytes long.
+// keywords have to be 1-79 latin-1 characters, callers pass fixed ascii ones
+pub fn embed_text(png: &[u8], entries: &[(String, String)]) -> Option<Vec<u8>> {
+  if !png.starts_with(SIGNATURE) || png.get(12..16) != Some(b"IHDR") {
+    return None;
+  }
+  let insert_at = SIGNATURE.len() + 12 + 13;
+
+  let mut output = Vec::with_capacity(png.len() + entries.len() * 64);
+  output.extend_from_slice(png.get(..insert_at)?);
+  for (keyword, text) in entries {
+    output.extend_from_slice(&text_chunk(keyword, text));
+  }
+  output.extend_from_slice(&png[insert_at..]);
+  Some(output)
+}
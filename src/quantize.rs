@@ -0,0 +1,131 @@
+// palette reduction with neuquant, written out as indexed png/gif so the file actually shrinks
+use crate::{create, encoding_error, FloatingImage};
+use color_quant::NeuQuant;
+use image::{ImageError, ImageFormat};
+use std::path::Path;
+
+const SAMPLE_FACTOR: i32 = 10; // 1 is best and slowest, 30 fastest; 10 is the usual middle
+
+// rgba entries, indexed by the values quantize returns
+pub type Palette = Vec<[u8; 4]>;
+
+// one palette index per pixel
+pub fn quantize(data: &[u8], width: u32, height: u32, colors: u16) -> (Vec<u8>, Palette) {
+  assert_eq!(data.len(), width as usize * height as usize * 4);
+  let quantizer = NeuQuant::new(SAMPLE_FACTOR, colors as usize, data);
+
+  let indices = data
+    .chunks_exact(4)
+    .map(|pixel| quantizer.index_of(pixel) as u8)
+    .collect();
+  let palette = quantizer
+    .color_map_rgba()
+    .chunks_exact(4)
+    .map(|c| [c[0], c[1], c[2], c[3]])
+    .collect();
+  (indices, palette)
+}
+
+fn save_indexed_png(
+  path: &Path,
+  width: u32,
+  height: u32,
+  indices: &[u8],
+  palette: &Palette,
+) -> Result<(), ImageError> {
+  let mut encoder = png::Encoder::new(create(path)?, width, height);
+  encoder.set_color(png::ColorType::Indexed);
+  encoder.set_depth(png::BitDepth::Eight);
+  encoder.set_palette(palette.iter().flat_map(|c| c[..3].to_vec()).collect());
+  encoder.set_trns(palette.iter().map(|c| c[3]).collect());
+
+  encoder
+    .write_header()
+    .and_then(|mut writer| writer.write_image_data(indices))
+    .map_err(|e| encoding_error(ImageFormat::Png, e))
+}
+
+// gif has no partial alpha: the most transparent entry becomes the transparent index
+// if it is under half opacity, every other entry is drawn opaque
+fn save_indexed_gif(
+  path: &Path,
+  (width, height): (u16, u16),
+  indices: &[u8],
+  palette: &Palette,
+) -> Result<(), ImageError> {
+  let transparent = palette
+    .iter()
+    .enumerate()
+    .min_by_key(|(_, c)| c[3])
+    .filter(|(_, c)| c[3] < 128)
+    .map(|(i, _)| i as u8);
+  let rgb: Vec<u8> = palette.iter().flat_map(|c| c[..3].to_vec()).collect();
+
+  let frame = gif::Frame::from_palette_pixels(width, height, indices, &rgb, transparent);
+  let mut encoder = gif::Encoder::new(create(path)?, width, height, &[])
+    .map_err(|e| encoding_error(ImageFormat::Gif, e))?;
+  encoder
+    .write_frame(&frame)
+    .map_err(|e| encoding_error(ImageFormat::Gif, e))
+}
+
+fn gif_dimensions(width: u32, height: u32) -> Result<(u16, u16), ImageError> {
+  match (u16::try_from(width), u16::try_from(height)) {
+    (Ok(width), Ok(height)) => Ok((width, height)),
+    _ => Err(encoding_error(
+      ImageFormat::Gif,
+      format!("{}x{} is over gif's 65535x65535 limit", width, height),
+    )),
+  }
+}
+
+// formats without an indexed mode still get the reduced colours, just stored as rgba
+pub fn save_quantized(
+  output: &FloatingImage,
+  path: &Path,
+  format: ImageFormat,
+  colors: u16,
+) -> Result<(), ImageError> {
+  // gif sizes are 16 bit, checked before the slow part
+  let gif_size = match format {
+    ImageFormat::Gif => Some(gif_dimensions(output.width, output.height)?),
+    _ => None,
+  };
+  let (indices, palette) = quantize(&output.data, output.width, output.height, colors);
+
+  match (format, gif_size) {
+    (ImageFormat::Png, _) => {
+      save_indexed_png(path, output.width, output.height, &indices, &palette)
+    }
+    (ImageFormat::Gif, Some(size)) => save_indexed_gif(path, size, &indices, &palette),
+    (_, _) => {
+      let data: Vec<u8> = indices.iter().flat_map(|&i| palette[i as usize]).collect();
+      image::save_buffer_with_format(
+        path,
+        &data,
+        output.width,
+        output.height,
+        image::ColorType::Rgba8,
+        format,
+      )
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn gif_over_16_bits_is_an_error() {
+    assert_eq!(gif_dimensions(65535, 1).unwrap(), (65535, 1));
+    let mut output = FloatingImage::new(65536, 1, String::new());
+    output.set_data(vec![0; 65536 * 4]).unwrap();
+    let path = std::env::temp_dir().join(format!("combiner-wide-{}.gif", std::process::id()));
+    assert!(matches!(
+      save_quantized(&output, &path, ImageFormat::Gif, 16),
+      Err(ImageError::Encoding(_))
+    ));
+    assert!(!path.exists());
+  }
+}
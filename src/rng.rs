@@ -0,0 +1,36 @@
+// small seeded xorshift generator, so runs are reproducible without pulling in a crate
+pub struct XorShift {
+  state: u64,
+}
+
+impl XorShift {
+  pub fn new(seed: u64) -> Self {
+    // xorshift gets stuck on an all-zero state
+    XorShift { state: seed.max(1) }
+  }
+
+  // a stream of its own per key (a row, say), whatever order the keys are visited in.
+  // splitmix64's finalizer spreads neighbouring keys apart first
+  pub fn keyed(seed: u64, key: u64) -> Self {
+    let mut z = seed ^ key.wrapping_mul(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    XorShift::new(z ^ (z >> 31))
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    let mut x = self.state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    self.state = x;
+    x
+  }
+
+  pub fn fill_bytes(&mut self, buffer: &mut [u8]) {
+    for chunk in buffer.chunks_mut(8) {
+      let bytes = self.next_u64().to_le_bytes();
+      chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+  }
+}
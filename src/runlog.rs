@@ -0,0 +1,232 @@
+// --log-command: one json line per run with everything needed to repeat it
+use crate::args::Args;
+use crate::ImageDataErrors;
+use image::{ColorType, ImageFormat, Rgba};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// what the run worked out on top of its arguments
+pub struct RunRecord<'a> {
+  pub inputs: Vec<(&'a str, (u32, u32))>, // path and size as read, before any resizing
+  pub output: (u32, u32),
+  pub format: ImageFormat,
+  pub color_type: Option<ColorType>, // None -> palette, from --quantize
+}
+
+// 64 bit fnv-1a, enough to tell whether an input changed between runs
+fn fnv1a(data: &[u8]) -> u64 {
+  data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+    (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+  })
+}
+
+fn string(value: &str) -> String {
+  let mut quoted = String::from("\"");
+  for c in value.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\r' => quoted.push_str("\\r"),
+      '\t' => quoted.push_str("\\t"),
+      c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+// enums go in by their rust name, there's nothing to gain from a second naming scheme
+fn name(value: impl std::fmt::Debug) -> String {
+  string(&format!("{:?}", value))
+}
+
+fn color(value: Rgba<u8>) -> String {
+  let [r, g, b, a] = value.0;
+  string(&format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a))
+}
+
+fn size((width, height): (u32, u32)) -> String {
+  format!("[{}, {}]", width, height)
+}
+
+fn optional<T>(value: Option<T>, to_json: impl Fn(T) -> String) -> String {
+  value.map_or_else(|| String::from("null"), to_json)
+}
+
+fn object(fields: &[(&str, String)]) -> String {
+  let fields: Vec<String> = fields
+    .iter()
+    .map(|(key, value)| format!("{}: {}", string(key), value))
+    .collect();
+  format!("{{{}}}", fields.join(", "))
+}
+
+fn array(values: impl Iterator<Item = String>) -> String {
+  format!("[{}]", values.collect::<Vec<String>>().join(", "))
+}
+
+// every option, defaults included, so the line doesn't depend on what the defaults were then
+fn args_json(args: &Args) -> String {
+  let text = |value: &String| string(value);
+  let number = |value: u64| value.to_string();
+  object(&[
+    ("image_1", string(&args.image_1)),
+    ("image_2", string(&args.image_2)),
+    ("output", string(&args.output)),
+    ("output_format", optional(args.output_format, name)),
+    ("preset", optional(args.preset, name)),
+    ("quality", optional(args.quality, |q| q.to_string())),
+    (
+      "max_dimension",
+      optional(args.max_dimension, |m| m.to_string()),
+    ),
+    ("filter", optional(args.filter, name)),
+    ("aa", args.aa.to_string()),
+    ("size_policy", name(args.size_policy)),
+    ("background_tile", args.background_tile.to_string()),
+    ("anchor", name(args.anchor)),
+    ("canvas", optional(args.canvas, size)),
+    ("blend_mode", name(args.blend_mode)),
+    ("rounding", name(args.rounding)),
+    ("mask", optional(args.mask.as_ref(), text)),
+    ("mask_from_diff", args.mask_from_diff.to_string()),
+    (
+      "mask_threshold",
+      optional(args.mask_threshold, |t| t.to_string()),
+    ),
+    ("mask_feather", args.mask_feather.to_string()),
+    ("alpha_from", optional(args.alpha_from.as_ref(), text)),
+    ("premultiply", args.premultiply.to_string()),
+    (
+      "drop_shadow",
+      optional(args.drop_shadow, |shadow| {
+        object(&[
+          (
+            "offset",
+            format!("[{}, {}]", shadow.offset.0, shadow.offset.1),
+          ),
+          ("blur", shadow.blur.to_string()),
+          ("opacity", shadow.opacity.to_string()),
+          ("color", color(shadow.color)),
+        ])
+      }),
+    ),
+    (
+      "output_bitdepth",
+      optional(args.output_bitdepth, |d| d.to_string()),
+    ),
+    ("color_type", optional(args.color_type, name)),
+    ("stats", args.stats.to_string()),
+    ("auto_align", args.auto_align.to_string()),
+    ("manifest", optional(args.manifest.as_ref(), text)),
+    ("fail_fast", args.fail_fast.to_string()),
+    (
+      "timeout_per_image",
+      optional(args.timeout_per_image, number),
+    ),
+    ("integer_scale", args.integer_scale.to_string()),
+    ("compare", args.compare.to_string()),
+    ("divider_width", args.divider_width.to_string()),
+    ("divider_color", color(args.divider_color)),
+    ("label_1", optional(args.label_1.as_ref(), text)),
+    ("label_2", optional(args.label_2.as_ref(), text)),
+    ("label_color", color(args.label_color)),
+    ("exif_preserve", args.exif_preserve.to_string()),
+    ("embed_sources", args.embed_sources.to_string()),
+    ("preview_scale", args.preview_scale.to_string()),
+    ("assume_srgb", args.assume_srgb.to_string()),
+    ("strict", args.strict.to_string()),
+    ("validate_only", args.validate_only.to_string()),
+    ("frame_1", args.frame_1.to_string()),
+    ("frame_2", args.frame_2.to_string()),
+    ("frame_blend", args.frame_blend.to_string()),
+    ("posterize", optional(args.posterize, |l| l.to_string())),
+    ("pixelate", optional(args.pixelate, |b| b.to_string())),
+    ("sort_pixels", optional(args.sort_pixels, name)),
+    ("sort_low", args.sort_low.to_string()),
+    ("sort_high", args.sort_high.to_string()),
+    (
+      "auto_contrast",
+      optional(args.auto_contrast, |c| c.to_string()),
+    ),
+    ("edge_1", args.edge_1.to_string()),
+    ("edge_2", args.edge_2.to_string()),
+    (
+      "round_corners",
+      optional(args.round_corners, |r| r.to_string()),
+    ),
+    (
+      "threshold",
+      optional(args.threshold, |t| match t {
+        Some(level) => level.to_string(),
+        None => string("auto"),
+      }),
+    ),
+    ("quantize", optional(args.quantize, |c| c.to_string())),
+    ("strip_alpha", args.strip_alpha.to_string()),
+    ("skip_empty", args.skip_empty.to_string()),
+    ("verify", args.verify.to_string()),
+    ("lead_sharper", args.lead_sharper.to_string()),
+    ("jitter", args.jitter.amount.to_string()),
+    ("seed", args.jitter.seed.to_string()),
+    ("split_output", optional(args.split_output.as_ref(), text)),
+    ("verbose", args.verbose.to_string()),
+    ("limit_memory", optional(args.limit_memory, number)),
+    ("bg_color", optional(args.bg_color, color)),
+    ("mirror", optional(args.mirror, name)),
+    ("grid", optional(args.grid, size)),
+    ("inputs", array(args.inputs.iter().map(text))),
+    ("grid_cell", optional(args.grid_cell, size)),
+    ("gutter", args.gutter.to_string()),
+    ("grid_bg", color(args.grid_bg)),
+    ("grid_truncate", args.grid_truncate.to_string()),
+  ])
+}
+
+// the inputs are read again for their hash; by now they're in the page cache
+fn input_json(path: &str, dimensions: (u32, u32)) -> Result<String, ImageDataErrors> {
+  let data = std::fs::read(path).map_err(ImageDataErrors::UnableToReadImageFromPath)?;
+  Ok(object(&[
+    ("path", string(path)),
+    ("fnv1a", string(&format!("{:016x}", fnv1a(&data)))),
+    ("size", size(dimensions)),
+  ]))
+}
+
+// append after a successful run; every batch job gets a line of its own
+pub fn append(path: &str, args: &Args, record: &RunRecord) -> Result<(), ImageDataErrors> {
+  let timestamp = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |elapsed| elapsed.as_secs());
+  let inputs = record
+    .inputs
+    .iter()
+    .map(|&(path, dimensions)| input_json(path, dimensions))
+    .collect::<Result<Vec<String>, _>>()?;
+  let line = object(&[
+    ("timestamp", timestamp.to_string()),
+    ("version", string(env!("CARGO_PKG_VERSION"))),
+    ("command", array(std::env::args().map(|arg| string(&arg)))),
+    ("args", args_json(args)),
+    ("inputs", array(inputs.into_iter())),
+    (
+      "output",
+      object(&[
+        ("path", string(&args.output)),
+        ("format", name(record.format)),
+        ("color_type", optional(record.color_type, name)),
+        ("size", size(record.output)),
+      ]),
+    ),
+  ]);
+
+  // one write per line, so an abandoned batch job finishing late can't split another's line
+  std::fs::OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+    .and_then(|mut file| file.write_all(format!("{}\n", line).as_bytes()))
+    .map_err(ImageDataErrors::UnableToWriteLog)
+}
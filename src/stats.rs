@@ -0,0 +1,77 @@
+// per-channel summary of an rgba buffer
+#[derive(Debug)]
+pub struct ChannelStats {
+  pub mean: [f64; 4],
+  pub min: [u8; 4],
+  pub max: [u8; 4],
+}
+
+pub fn channel_stats(data: &[u8]) -> ChannelStats {
+  let mut sum = [0u64; 4];
+  let mut min = [u8::MAX; 4];
+  let mut max = [u8::MIN; 4];
+
+  for pixel in data.chunks_exact(4) {
+    for i in 0..4 {
+      sum[i] += pixel[i] as u64;
+      min[i] = min[i].min(pixel[i]);
+      max[i] = max[i].max(pixel[i]);
+    }
+  }
+
+  // an empty buffer has no min/max either
+  let pixels = (data.len() / 4) as f64;
+  if pixels == 0.0 {
+    return ChannelStats {
+      mean: [0.0; 4],
+      min: [0; 4],
+      max: [0; 4],
+    };
+  }
+
+  let mut mean = [0f64; 4];
+  for i in 0..4 {
+    mean[i] = sum[i] as f64 / pixels;
+  }
+  ChannelStats { mean, min, max }
+}
+
+pub fn print_stats(label: &str, stats: &ChannelStats) {
+  println!("{}", label);
+  println!("  channel     mean  min  max");
+  for (i, channel) in ["r", "g", "b", "a"].iter().enumerate() {
+    println!(
+      "  {:<7} {:>8.2} {:>4} {:>4}",
+      channel, stats.mean[i], stats.min[i], stats.max[i]
+    );
+  }
+}
+
+// every pixel has alpha 0, so a blend with it only fades or hides the other image
+pub fn is_fully_transparent(data: &[u8]) -> bool {
+  data.chunks_exact(4).all(|pixel| pixel[3] == 0)
+}
+
+// variance of the laplacian of luma: blurry images have few sharp edges, so a low value.
+// border pixels are skipped since they miss neighbours
+pub fn sharpness(data: &[u8], width: u32, height: u32) -> f64 {
+  let (width, height) = (width as usize, height as usize);
+  if width < 3 || height < 3 {
+    return 0.0;
+  }
+
+  let luma: Vec<f64> = data
+    .chunks_exact(4)
+    .map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64)
+    .collect();
+  let mut responses = Vec::with_capacity((width - 2) * (height - 2));
+  for y in 1..height - 1 {
+    for x in 1..width - 1 {
+      let i = y * width + x;
+      responses.push(luma[i - 1] + luma[i + 1] + luma[i - width] + luma[i + width] - 4.0 * luma[i]);
+    }
+  }
+
+  let mean = responses.iter().sum::<f64>() / responses.len() as f64;
+  responses.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / responses.len() as f64
+}
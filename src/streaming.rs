@@ -0,0 +1,161 @@
+// row by row png encoding, so a huge output isn't held a second time in converted,
+// filtered and compressed form while it is saved
+use crate::{convert_color_type, create, encoding_error, FloatingImage};
+use image::{ColorType, ImageError, ImageFormat};
+use miniz_oxide::deflate::core::{
+  compress, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+use std::io::Write;
+use std::path::Path;
+
+// png outputs with at least this many pixels take the streaming path (64 MB of rgba8)
+pub const STREAM_PIXELS: u64 = 4096 * 4096;
+const IDAT_SIZE: usize = 256 * 1024; // compressed bytes per IDAT chunk
+
+fn png_color(color_type: ColorType) -> (png::ColorType, png::BitDepth) {
+  let color = match color_type {
+    ColorType::L8 | ColorType::L16 => png::ColorType::Grayscale,
+    ColorType::La8 | ColorType::La16 => png::ColorType::GrayscaleAlpha,
+    ColorType::Rgb8 | ColorType::Rgb16 => png::ColorType::RGB,
+    _ => png::ColorType::RGBA,
+  };
+  match color_type.bytes_per_pixel() / color_type.channel_count() {
+    2 => (color, png::BitDepth::Sixteen),
+    _ => (color, png::BitDepth::Eight),
+  }
+}
+
+// feed `input` to the compressor and queue up what comes out; Finish runs until the
+// stream is closed, anything else until the input is used up
+fn deflate(
+  compressor: &mut CompressorOxide,
+  mut input: &[u8],
+  flush: TDEFLFlush,
+  pending: &mut Vec<u8>,
+) -> Result<(), ImageError> {
+  let mut out = [0u8; 64 * 1024];
+  loop {
+    let (status, consumed, written) = compress(compressor, input, &mut out, flush);
+    pending.extend_from_slice(&out[..written]);
+    input = &input[consumed..];
+    match status {
+      TDEFLStatus::Done => return Ok(()),
+      TDEFLStatus::Okay if flush != TDEFLFlush::Finish && input.is_empty() => return Ok(()),
+      TDEFLStatus::Okay => continue,
+      status => {
+        return Err(encoding_error(
+          ImageFormat::Png,
+          format!("deflate failed: {:?}", status),
+        ))
+      }
+    }
+  }
+}
+
+pub fn save_png(
+  output: &FloatingImage,
+  path: &Path,
+  color_type: ColorType,
+) -> Result<(), ImageError> {
+  let mut file = create(path)?;
+  let mut encoder = png::Encoder::new(&mut file, output.width, output.height);
+  let (color, depth) = png_color(color_type);
+  encoder.set_color(color);
+  encoder.set_depth(depth);
+  let mut writer = encoder
+    .write_header()
+    .map_err(|e| encoding_error(ImageFormat::Png, e))?;
+
+  let mut compressor = CompressorOxide::new(create_comp_flags_from_zip_params(6, 15, 0));
+  let mut pending = Vec::with_capacity(IDAT_SIZE * 2);
+  let bpp = color_type.bytes_per_pixel() as usize;
+  let mut line = Vec::new();
+
+  for row in output.data.chunks_exact(output.width as usize * 4) {
+    let mut raw = match color_type {
+      ColorType::Rgba8 => row.to_vec(),
+      _ => convert_color_type(row, ColorType::Rgba8, color_type, (output.width, 1)),
+    };
+    // png stores 16 bit samples big endian
+    if depth == png::BitDepth::Sixteen {
+      for sample in raw.chunks_exact_mut(2) {
+        let value = u16::from_ne_bytes([sample[0], sample[1]]);
+        sample.copy_from_slice(&value.to_be_bytes());
+      }
+    }
+
+    // sub filter: each byte minus the same byte of the pixel to its left
+    line.clear();
+    line.push(1);
+    line.extend((0..raw.len()).map(|i| match i < bpp {
+      true => raw[i],
+      false => raw[i].wrapping_sub(raw[i - bpp]),
+    }));
+
+    deflate(&mut compressor, &line, TDEFLFlush::None, &mut pending)?;
+    if pending.len() >= IDAT_SIZE {
+      writer
+        .write_chunk(*b"IDAT", &pending)
+        .map_err(|e| encoding_error(ImageFormat::Png, e))?;
+      pending.clear();
+    }
+  }
+
+  deflate(&mut compressor, &[], TDEFLFlush::Finish, &mut pending)?;
+  writer
+    .write_chunk(*b"IDAT", &pending)
+    .map_err(|e| encoding_error(ImageFormat::Png, e))?;
+  drop(writer); // writes IEND
+  file.flush()?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::rng::XorShift;
+  use crate::{convert_color_type, save_image, FloatingImage};
+  use image::{ColorType, ImageFormat};
+
+  // a threshold of 0 streams every png, u64::MAX streams none; both have to decode to
+  // the same samples the image encoder is handed. the combined buffer is rgba8, so 16 bit
+  // samples are v * 257 and read the same in either byte order
+  #[test]
+  fn streamed_png_round_trips() {
+    let mut rng = XorShift::new(7);
+    // 300x300 of noise doesn't compress, so the larger types span several IDAT chunks
+    for (width, height) in [(5, 3), (300, 300)] {
+      let mut data = vec![0u8; width as usize * height as usize * 4];
+      rng.fill_bytes(&mut data);
+      let mut output = FloatingImage::new(width, height, String::new());
+      output.set_data(data).unwrap();
+
+      for color_type in [ColorType::Rgba8, ColorType::Rgba16, ColorType::La8] {
+        let expected =
+          convert_color_type(&output.data, ColorType::Rgba8, color_type, (width, height));
+        for stream_pixels in [0, u64::MAX] {
+          let path = std::env::temp_dir().join(format!(
+            "combiner-stream-{}-{}x{}-{:?}-{}.png",
+            std::process::id(),
+            width,
+            height,
+            color_type,
+            stream_pixels
+          ));
+          save_image(
+            &output,
+            &path,
+            ImageFormat::Png,
+            color_type,
+            None,
+            stream_pixels,
+          )
+          .unwrap();
+          let decoded = image::open(&path).unwrap();
+          std::fs::remove_file(&path).unwrap();
+          assert_eq!(decoded.color(), color_type);
+          assert_eq!(decoded.as_bytes(), &expected[..]);
+        }
+      }
+    }
+  }
+}
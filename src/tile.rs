@@ -0,0 +1,81 @@
+// --background-tile: image 2 repeated over the canvas, image 1 placed on it at its own size
+use image::{imageops, DynamicImage, GenericImageView, RgbaImage};
+
+// where on the canvas the foreground sits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Anchor {
+  TopLeft,
+  Top,
+  TopRight,
+  Left,
+  #[default]
+  Center,
+  Right,
+  BottomLeft,
+  Bottom,
+  BottomRight,
+}
+
+impl Anchor {
+  // the foreground's top left corner on the canvas, negative when it is larger.
+  // centring rounds towards the top left
+  fn offset(self, canvas: (u32, u32), foreground: (u32, u32)) -> (i64, i64) {
+    let (free_x, free_y) = (
+      canvas.0 as i64 - foreground.0 as i64,
+      canvas.1 as i64 - foreground.1 as i64,
+    );
+    // 0 -> start, 1 -> middle, 2 -> end, in halves of the free space
+    let (column, row) = match self {
+      Anchor::TopLeft => (0, 0),
+      Anchor::Top => (1, 0),
+      Anchor::TopRight => (2, 0),
+      Anchor::Left => (0, 1),
+      Anchor::Center => (1, 1),
+      Anchor::Right => (2, 1),
+      Anchor::BottomLeft => (0, 2),
+      Anchor::Bottom => (1, 2),
+      Anchor::BottomRight => (2, 2),
+    };
+    (
+      (free_x * column).div_euclid(2),
+      (free_y * row).div_euclid(2),
+    )
+  }
+}
+
+// the two canvas sized layers for an over blend: the foreground on a transparent canvas,
+// and the background repeated from the top left corner. a foreground larger than the
+// canvas is cropped to it
+pub fn layers(
+  foreground: &DynamicImage,
+  background: &DynamicImage,
+  canvas: (u32, u32),
+  anchor: Anchor,
+) -> (DynamicImage, DynamicImage) {
+  let (width, height) = canvas;
+  let mut tiled = RgbaImage::new(width, height);
+  imageops::tile(&mut tiled, &background.to_rgba8());
+
+  let (x, y) = anchor.offset(canvas, foreground.dimensions());
+  // the part of the foreground hanging off the top or left is cut away first,
+  // replace clips the bottom and right by itself
+  let (crop_x, crop_y) = ((-x).max(0) as u32, (-y).max(0) as u32);
+  let visible = foreground.crop_imm(
+    crop_x,
+    crop_y,
+    foreground.width().saturating_sub(crop_x),
+    foreground.height().saturating_sub(crop_y),
+  );
+  let mut placed = RgbaImage::new(width, height);
+  imageops::replace(
+    &mut placed,
+    &visible.to_rgba8(),
+    x.max(0) as u32,
+    y.max(0) as u32,
+  );
+
+  (
+    DynamicImage::ImageRgba8(placed),
+    DynamicImage::ImageRgba8(tiled),
+  )
+}